@@ -0,0 +1,113 @@
+use super::{align_up, GrowFn};
+use core::alloc::Layout;
+use core::ptr;
+
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+    grow: Option<GrowFn>,
+}
+
+impl BumpAllocator {
+    // create empty bump allocator
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+            grow: None,
+        }
+    }
+
+    // Initialize bump allocator within bounds
+    // Unsafe: caller must ensure given mem range is unused
+    // Must only be called once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    /// Lets the heap grow through `grow` once it is exhausted.
+    pub fn set_grow_fn(&mut self, grow: GrowFn) {
+        self.grow = Some(grow);
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    /// Bytes handed out since the last reset, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.next - self.heap_start
+    }
+
+    /// Frees every allocation at once.
+    ///
+    /// Unsafe because memory handed out before must not be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        self.next = self.heap_start;
+        self.allocations = 0;
+    }
+
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // round up 'next' address to alignment specified by layout
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(), // out of memory
+        };
+
+        if alloc_end > self.heap_end {
+            // map more memory directly above the heap
+            match super::grow(self.grow, self.heap_end, alloc_end - self.heap_end) {
+                Some(grown) => self.heap_end += grown,
+                None => return ptr::null_mut(), // out of memory
+            }
+        }
+
+        self.next = alloc_end;
+        self.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    /// Resizes an allocation without moving it, returns false if that's not possible.
+    ///
+    /// Shrinking always works, growing only for the most recent allocation.
+    pub unsafe fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let start = ptr as usize;
+        let is_last = start + layout.size() == self.next;
+        if !is_last {
+            // the space given up by shrinking is only reclaimed on reset
+            return new_size <= layout.size();
+        }
+
+        let new_end = match start.checked_add(new_size) {
+            Some(end) => end,
+            None => return false,
+        };
+        if new_end > self.heap_end {
+            match super::grow(self.grow, self.heap_end, new_end - self.heap_end) {
+                Some(grown) => self.heap_end += grown,
+                None => return false,
+            }
+        }
+        self.next = new_end;
+        true
+    }
+
+    pub unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use core::alloc::Layout;
+use core::{fmt, mem, ptr, slice};
+
+// space at the start of every allocation left to the allocator's own free list
+// links, so they don't overwrite the header of a freed allocation
+const LINK_SPACE: usize = 16;
+const HEADER_SIZE: usize = mem::size_of::<Header>();
+/// Size of the guard areas directly before and after every allocation.
+pub const RED_ZONE_SIZE: usize = 16;
+
+/// Byte pattern of the red zones.
+pub const RED_ZONE_BYTE: u8 = 0xfd;
+/// Byte pattern written into freed memory.
+pub const POISON_BYTE: u8 = 0xdd;
+/// Byte pattern of newly allocated memory, to make uninitialized reads stand out.
+pub const UNINIT_BYTE: u8 = 0xcd;
+
+const ALLOCATED: u32 = 0xa110_c8ed;
+const FREED: u32 = 0xf4ee_dead;
+
+// stored directly in front of the front red zone
+#[repr(C)]
+struct Header {
+    magic: u32,
+    align: u32,
+    size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapErrorKind {
+    DoubleFree,
+    /// The pointer was never handed out, or the header in front of it got overwritten.
+    InvalidPointer,
+    /// Freed with a different layout than it was allocated with.
+    LayoutMismatch {
+        allocated: Layout,
+    },
+    /// A red zone byte was overwritten, `offset` is relative to the allocation start.
+    RedZone {
+        offset: isize,
+    },
+}
+
+/// Heap corruption found when freeing `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapError {
+    pub addr: usize,
+    pub layout: Layout,
+    pub kind: HeapErrorKind,
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            HeapErrorKind::DoubleFree => write!(f, "double free of {:#x}", self.addr)?,
+            HeapErrorKind::InvalidPointer => write!(f, "invalid free of {:#x}", self.addr)?,
+            HeapErrorKind::LayoutMismatch { allocated } => write!(
+                f,
+                "{:#x} freed with wrong layout, allocated with {:?}",
+                self.addr, allocated
+            )?,
+            HeapErrorKind::RedZone { offset } => write!(
+                f,
+                "red zone of {:#x} overwritten at offset {}",
+                self.addr, offset
+            )?,
+        }
+        write!(f, " ({:?})", self.layout)
+    }
+}
+
+// distance from the start of the underlying allocation to the returned pointer
+fn front_size(layout: Layout) -> usize {
+    super::align_up(LINK_SPACE + HEADER_SIZE + RED_ZONE_SIZE, layout.align())
+}
+
+/// Layout of the underlying allocation for an allocation of `layout`,
+/// including header and red zones.
+pub fn outer_layout(layout: Layout) -> Layout {
+    let size = front_size(layout) + layout.size() + RED_ZONE_SIZE;
+    let align = layout.align().max(mem::align_of::<Header>());
+    Layout::from_size_align(size, align).expect("debug layout overflow")
+}
+
+/// Sets up header and red zones in `base`, an allocation of `outer_layout(layout)`,
+/// and returns the pointer to hand out. Null stays null.
+pub unsafe fn init_allocation(base: *mut u8, layout: Layout) -> *mut u8 {
+    if base.is_null() {
+        return base;
+    }
+    let ptr = base.add(front_size(layout));
+    header(ptr).write(Header {
+        magic: ALLOCATED,
+        align: layout.align() as u32,
+        size: layout.size(),
+    });
+    ptr::write_bytes(ptr.sub(RED_ZONE_SIZE), RED_ZONE_BYTE, RED_ZONE_SIZE);
+    ptr::write_bytes(ptr, UNINIT_BYTE, layout.size());
+    ptr::write_bytes(ptr.add(layout.size()), RED_ZONE_BYTE, RED_ZONE_SIZE);
+    ptr
+}
+
+/// Validates a pointer about to be freed and poisons its memory.
+///
+/// A double free is recognized by the header left behind by the first free,
+/// so it goes unnoticed if the memory was allocated again in between.
+///
+/// Returns the start and layout of the underlying allocation to free.
+pub unsafe fn check_free(ptr: *mut u8, layout: Layout) -> Result<(*mut u8, Layout), HeapError> {
+    let error = |kind| HeapError {
+        addr: ptr as usize,
+        layout,
+        kind,
+    };
+
+    let header = &mut *header(ptr);
+    match header.magic {
+        ALLOCATED => {}
+        FREED => return Err(error(HeapErrorKind::DoubleFree)),
+        _ => return Err(error(HeapErrorKind::InvalidPointer)),
+    }
+    let allocated = Layout::from_size_align(header.size, header.align as usize)
+        .map_err(|_| error(HeapErrorKind::InvalidPointer))?;
+    if allocated != layout {
+        return Err(error(HeapErrorKind::LayoutMismatch { allocated }));
+    }
+
+    let front = slice::from_raw_parts(ptr.sub(RED_ZONE_SIZE), RED_ZONE_SIZE);
+    if let Some(i) = front.iter().position(|&b| b != RED_ZONE_BYTE) {
+        let offset = i as isize - RED_ZONE_SIZE as isize;
+        return Err(error(HeapErrorKind::RedZone { offset }));
+    }
+    let back = slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE_SIZE);
+    if let Some(i) = back.iter().position(|&b| b != RED_ZONE_BYTE) {
+        let offset = (layout.size() + i) as isize;
+        return Err(error(HeapErrorKind::RedZone { offset }));
+    }
+
+    header.magic = FREED;
+    ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+    Ok((ptr.sub(front_size(layout)), outer_layout(layout)))
+}
+
+unsafe fn header(ptr: *mut u8) -> *mut Header {
+    ptr.sub(RED_ZONE_SIZE + HEADER_SIZE) as *mut Header
+}
@@ -0,0 +1,234 @@
+#[cfg(feature = "heap-debug")]
+use super::debug;
+use super::GrowFn;
+use core::alloc::Layout;
+use core::{mem, ptr, ptr::NonNull};
+use linked_list_allocator::Heap;
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+// sizes used for alignment. must be powers of 2
+// 8 min because each block must be able to hold 64bit pointer to next block
+// fall back allocator used for larger than 2048
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 512, 1024, 2048];
+
+/// Usage numbers of a `FixedSizeBlockAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Allocations served by each block size, in `BLOCK_SIZES` order.
+    pub class_hits: [u64; BLOCK_SIZES.len()],
+    /// Allocations passed on to the fallback allocator, either too large for a
+    /// block or a new block for an empty list.
+    pub fallback_allocations: u64,
+    /// Bytes handed out by the fallback allocator, including blocks.
+    pub fallback_bytes_in_use: usize,
+}
+
+/// Serves small allocations from one free list per block size and everything
+/// else from a linked list fallback allocator.
+///
+/// Freed blocks stay on their free list. They are only given back to the fallback
+/// allocator once it fails to serve an allocation, right before the heap grows.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    // None until init, `Heap::empty` is only a const fn with nightly features
+    fallback_allocator: Option<Heap>,
+    class_hits: [u64; BLOCK_SIZES.len()],
+    fallback_allocations: u64,
+    grow: Option<GrowFn>,
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: None,
+            class_hits: [0; BLOCK_SIZES.len()],
+            fallback_allocations: 0,
+            grow: None,
+        }
+    }
+
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator = Some(Heap::new(heap_start, heap_size));
+    }
+
+    /// Lets the heap grow through `grow` once it is exhausted.
+    pub fn set_grow_fn(&mut self, grow: GrowFn) {
+        self.grow = Some(grow);
+    }
+
+    /// Size of the heap managed by the fallback allocator in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.fallback_allocator.as_ref().map_or(0, Heap::size)
+    }
+
+    #[cfg(not(feature = "heap-debug"))]
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate_raw(layout)
+    }
+
+    // surround every allocation with a header and red zones
+    #[cfg(feature = "heap-debug")]
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let base = self.allocate_raw(debug::outer_layout(layout));
+        debug::init_allocation(base, layout)
+    }
+
+    /// Frees an allocation made with `allocate`.
+    ///
+    /// With the `heap-debug` feature, corruption like a double free or an
+    /// overwritten red zone is detected here and causes a panic. A double free
+    /// is only caught while the block is still free: once it has been handed
+    /// out again, the second free looks like a valid free of the new allocation.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-debug")]
+        let (ptr, layout) = match debug::check_free(ptr, layout) {
+            Ok(outer) => outer,
+            Err(err) => panic!("heap corruption: {}", err),
+        };
+        self.deallocate_raw(ptr, layout)
+    }
+
+    /// Resizes an allocation without moving it, returns false if that's not possible.
+    ///
+    /// Works as long as the new size still maps to the same block size.
+    /// Allocations of the fallback allocator are always moved.
+    pub unsafe fn resize_in_place(
+        &mut self,
+        _ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        // never in debug mode, the header and red zones would have to move, and
+        // moving checks the old allocation on the way
+        if cfg!(feature = "heap-debug") {
+            return false;
+        }
+        let new_layout = match Layout::from_size_align(new_size, layout.align()) {
+            Ok(new_layout) => new_layout,
+            Err(_) => return false,
+        };
+        match (list_index(&layout), list_index(&new_layout)) {
+            (Some(index), Some(new_index)) => index == new_index,
+            _ => false,
+        }
+    }
+
+    unsafe fn allocate_raw(&mut self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => {
+                self.class_hits[index] += 1;
+                match self.list_heads[index].take() {
+                    Some(node) => {
+                        self.list_heads[index] = node.next.take();
+                        node as *mut ListNode as *mut u8
+                    }
+                    None => {
+                        // no block exists in list => allocate new block
+                        let block_size = BLOCK_SIZES[index];
+                        // only works if all block sizes are a power of 2
+                        let block_align = block_size;
+                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                        self.fallback_allocator(layout)
+                    }
+                }
+            }
+            None => self.fallback_allocator(layout),
+        }
+    }
+
+    unsafe fn deallocate_raw(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+                // verify that block has size and alignment required for storing node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => {
+                let ptr = NonNull::new(ptr).unwrap();
+                self.fallback_heap().deallocate(ptr, layout);
+            }
+        }
+    }
+
+    pub fn block_stats(&self) -> BlockStats {
+        BlockStats {
+            class_hits: self.class_hits,
+            fallback_allocations: self.fallback_allocations,
+            fallback_bytes_in_use: self.fallback_allocator.as_ref().map_or(0, Heap::used),
+        }
+    }
+
+    fn fallback_allocator(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocations += 1;
+        if self.fallback_allocator.is_none() {
+            return ptr::null_mut(); // not initialized
+        }
+
+        if let Ok(ptr) = self.fallback_heap().allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // free blocks are only reused for their own size, give them back so they
+        // can merge into larger holes before growing the heap
+        // not done any earlier, the free lists are the fast path for small blocks
+        if self.reclaim_free_blocks() {
+            if let Ok(ptr) = self.fallback_heap().allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+        }
+
+        // heap exhausted -> map more pages above it and try again
+        // size + align is enough even if the new space needs padding for alignment
+        let heap_top = self.fallback_heap().top();
+        match super::grow(self.grow, heap_top, layout.size() + layout.align()) {
+            Some(grown) => unsafe { self.fallback_heap().extend(grown) },
+            None => return ptr::null_mut(),
+        }
+
+        match self.fallback_heap().allocate_first_fit(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    fn fallback_heap(&mut self) -> &mut Heap {
+        self.fallback_allocator
+            .as_mut()
+            .expect("allocator not initialized")
+    }
+
+    // hand every block on the free lists back to the fallback allocator
+    // returns false if all lists were empty
+    fn reclaim_free_blocks(&mut self) -> bool {
+        let mut reclaimed = false;
+        for (index, &block_size) in BLOCK_SIZES.iter().enumerate() {
+            // same layout the block was allocated from the fallback allocator with
+            let layout = Layout::from_size_align(block_size, block_size).unwrap();
+            while let Some(node) = self.list_heads[index].take() {
+                self.list_heads[index] = node.next.take();
+                let ptr = NonNull::from(node).cast::<u8>();
+                unsafe { self.fallback_heap().deallocate(ptr, layout) };
+                reclaimed = true;
+            }
+        }
+        reclaimed
+    }
+}
+
+// choose right block size
+// return index into size array
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
@@ -0,0 +1,34 @@
+//! Allocator algorithms of the kernel heap.
+//!
+//! They only work on the memory range they are given and leave mapping more of
+//! it to a `GrowFn`, so they can be tested on the host against a plain byte
+//! array. The `[unstable]` table of the kernel's `.cargo/config` is ignored on
+//! stable, so from this directory:
+//!
+//! ```text
+//! cargo +stable test --target x86_64-unknown-linux-gnu
+//! ```
+#![no_std]
+
+pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
+pub mod fixed_size_block;
+pub mod linked_list;
+pub mod tlsf;
+
+/// Maps at least `min_size` more bytes directly above `heap_top`, returns the
+/// number of bytes mapped or None if the heap can't grow.
+///
+/// Called with the allocator locked, so it must not allocate itself.
+pub type GrowFn = fn(heap_top: usize, min_size: usize) -> Option<usize>;
+
+// allocators without a GrowFn have a fixed size
+fn grow(grow: Option<GrowFn>, heap_top: usize, min_size: usize) -> Option<usize> {
+    grow.and_then(|grow| grow(heap_top, min_size))
+}
+
+/// Aligns `addr` upwards to `align`, which must be a power of two.
+pub const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
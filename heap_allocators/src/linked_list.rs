@@ -0,0 +1,318 @@
+use super::{align_up, GrowFn};
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+/// How `LinkedListAllocator` picks a free region for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+    /// Take the lowest addressed region that fits, fast.
+    FirstFit,
+    /// Take the smallest region that fits, keeps large regions intact for longer.
+    BestFit,
+}
+
+// free regions are kept sorted by address, so neighbours can be merged
+pub struct LinkedListAllocator {
+    head: ListNode,
+    policy: FitPolicy,
+    heap_start: usize,
+    heap_end: usize,
+    grow: Option<GrowFn>,
+}
+
+impl LinkedListAllocator {
+    // construct empty linked list allocator
+    pub const fn new() -> Self {
+        Self::with_policy(FitPolicy::FirstFit)
+    }
+
+    pub const fn with_policy(policy: FitPolicy) -> Self {
+        Self {
+            head: ListNode::new(0),
+            policy,
+            heap_start: 0,
+            heap_end: 0,
+            grow: None,
+        }
+    }
+
+    /// Lets the heap grow through `grow` once it is exhausted.
+    pub fn set_grow_fn(&mut self, grow: GrowFn) {
+        self.grow = Some(grow);
+    }
+
+    pub fn set_policy(&mut self, policy: FitPolicy) {
+        self.policy = policy;
+    }
+
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        let mut found = self.find_region(size, align);
+        // enough even if the new space needs padding for alignment
+        if found.is_none() && self.grow(size + align + mem::size_of::<ListNode>()) {
+            found = self.find_region(size, align);
+        }
+
+        if let Some((region, alloc_start)) = found {
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            // padding in front of the allocation stays free as well
+            if alloc_start > region_start {
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+            let excess_size = region_end - alloc_end;
+            if excess_size > 0 {
+                self.add_free_region(alloc_end, excess_size);
+            }
+            alloc_start as *mut u8
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+
+        self.add_free_region(ptr as usize, size)
+    }
+
+    /// Resizes an allocation without moving it, returns false if that's not possible.
+    ///
+    /// Growing takes space from the free region directly behind the allocation,
+    /// or maps more memory if the allocation is at the end of the heap.
+    pub unsafe fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let new_layout = match Layout::from_size_align(new_size, layout.align()) {
+            Ok(new_layout) => new_layout,
+            Err(_) => return false,
+        };
+        let (size, _) = Self::size_align(layout);
+        let (new_size, _) = Self::size_align(new_layout);
+        let end = ptr as usize + size;
+
+        if new_size <= size {
+            let excess_size = size - new_size;
+            if excess_size == 0 {
+                return true;
+            }
+            // the cut off end must be able to hold a ListNode
+            if excess_size < mem::size_of::<ListNode>() {
+                return false;
+            }
+            self.add_free_region(end - excess_size, excess_size);
+            return true;
+        }
+
+        let needed = new_size - size;
+        let mut free = self.free_region_at(end);
+        if free < needed && end + free == self.heap_end && self.grow(needed - free) {
+            // the new memory merged into the free region behind the allocation
+            free = self.free_region_at(end);
+        }
+        let excess_size = free.saturating_sub(needed);
+        if free < needed || (excess_size > 0 && excess_size < mem::size_of::<ListNode>()) {
+            return false;
+        }
+
+        self.remove_region_at(end);
+        if excess_size > 0 {
+            self.add_free_region(end + needed, excess_size);
+        }
+        true
+    }
+
+    // size of the free region starting at `addr`, 0 if there is none
+    fn free_region_at(&self, addr: usize) -> usize {
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            if region.start_addr() == addr {
+                return region.size;
+            }
+            if region.start_addr() > addr {
+                break;
+            }
+            current = region;
+        }
+        0
+    }
+
+    // remove the free region starting at `addr` from the list
+    fn remove_region_at(&mut self, addr: usize) {
+        let mut current = &mut self.head;
+        loop {
+            match current.next {
+                Some(ref region) if region.start_addr() == addr => {
+                    let region = current.next.take().unwrap();
+                    current.next = region.next.take();
+                    return;
+                }
+                Some(ref region) if region.start_addr() < addr => {
+                    current = current.next.as_mut().unwrap()
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // map at least `min_size` more bytes above the heap and add them as free region
+    fn grow(&mut self, min_size: usize) -> bool {
+        match super::grow(self.grow, self.heap_end, min_size) {
+            Some(grown) => {
+                // merges with the last free region if that one reaches the heap end
+                unsafe { self.add_free_region(self.heap_end, grown) };
+                self.heap_end += grown;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // add given region to the list at its address, merging it with directly
+    // adjacent free regions
+    unsafe fn add_free_region(&mut self, addr: usize, mut size: usize) {
+        // ensure region can hold list node
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        // the head is not a real region and must never be merged with
+        let head_addr = self.head.start_addr();
+
+        // find the last region before addr
+        let mut current = &mut self.head;
+        loop {
+            match current.next {
+                Some(ref next) if next.start_addr() < addr => {
+                    current = current.next.as_mut().unwrap()
+                }
+                _ => break,
+            }
+        }
+
+        // merge with the following region
+        let mut next = current.next.take();
+        if let Some(node) = next.take() {
+            debug_assert!(addr + size <= node.start_addr(), "freed region overlaps");
+            if addr + size == node.start_addr() {
+                size += node.size;
+                next = node.next.take();
+            } else {
+                next = Some(node);
+            }
+        }
+
+        // merge with the previous region, or insert a new node after it
+        if current.start_addr() != head_addr && current.end_addr() == addr {
+            current.size += size;
+            current.next = next;
+        } else {
+            let mut node = ListNode::new(size);
+            node.next = next;
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(node);
+            current.next = Some(&mut *node_ptr)
+        }
+    }
+
+    // looks for appropriate region according to the policy and removes it from free list
+    // returns list node and start addr of allocation
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        // node before the best region so far, its size and the allocation start in it
+        let mut best: Option<(*mut ListNode, usize, usize)> = None;
+        // pointer to current list node, updated for each iteration
+        let mut current: *mut ListNode = &mut self.head;
+
+        unsafe {
+            while let Some(region) = &(*current).next {
+                if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                    if best.map_or(true, |(_, best_size, _)| region.size < best_size) {
+                        best = Some((current, region.size, alloc_start));
+                    }
+                    // can't do better than the first fit or an exact fit
+                    if self.policy == FitPolicy::FirstFit || region.size == size {
+                        break;
+                    }
+                }
+                current = (*current).next.as_deref_mut().unwrap();
+            }
+
+            // region suitable for allocation -> remove node from list
+            let (previous, _, alloc_start) = best?;
+            let region = (*previous).next.take().unwrap();
+            (*previous).next = region.next.take();
+            Some((region, alloc_start))
+        }
+    }
+
+    // try to use given region for allocation
+    // on success: returns start addr of allocation
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start > region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            // padding in front too small to hold a ListNode, move on to the next
+            // aligned address
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > region.end_addr() {
+            // region too small
+            return Err(());
+        }
+
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            // rest of region too small to hold ListNode
+            // required because allocation splits region into used and free parts
+            return Err(());
+        }
+
+        // region good for allocation
+        Ok(alloc_start)
+    }
+
+    // adjust layout so region can store ListNode as well
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
@@ -0,0 +1,375 @@
+use super::{align_up, GrowFn};
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+// blocks start and end on this alignment
+const ALIGN: usize = 16;
+const ALIGN_LOG2: usize = 4;
+
+// every first level class (a power of two range) is split into SL_COUNT lists
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+
+// sizes below SMALL_BLOCK all go into the first level 0, one list per ALIGN bytes
+const FL_SHIFT: usize = SL_LOG2 + ALIGN_LOG2;
+const SMALL_BLOCK: usize = 1 << FL_SHIFT;
+// blocks up to 2^FL_MAX_LOG2 bytes
+const FL_MAX_LOG2: usize = 40;
+const FL_COUNT: usize = FL_MAX_LOG2 - FL_SHIFT + 1;
+
+const HEADER_SIZE: usize = mem::size_of::<BlockHeader>();
+// free blocks hold their list links in the payload
+const MIN_BLOCK_SIZE: usize = mem::size_of::<FreeLinks>();
+
+// low bit of BlockHeader::size, sizes are multiples of ALIGN
+const FREE: usize = 1;
+
+// in front of every block, used or free
+#[repr(C)]
+struct BlockHeader {
+    // physically previous block, null for the first one
+    prev_phys: *mut BlockHeader,
+    // payload size, FREE is set while the block is on a free list
+    size: usize,
+}
+
+// stored in the payload of free blocks
+#[repr(C)]
+struct FreeLinks {
+    next: *mut BlockHeader,
+    prev: *mut BlockHeader,
+}
+
+impl BlockHeader {
+    fn size(&self) -> usize {
+        self.size & !FREE
+    }
+
+    fn set_size(&mut self, size: usize) {
+        self.size = size | (self.size & FREE);
+    }
+
+    fn is_free(&self) -> bool {
+        self.size & FREE != 0
+    }
+
+    fn payload(&self) -> *mut u8 {
+        (self as *const Self as usize + HEADER_SIZE) as *mut u8
+    }
+
+    fn next_phys(&self) -> *mut BlockHeader {
+        (self.payload() as usize + self.size()) as *mut BlockHeader
+    }
+
+    fn links(&self) -> *mut FreeLinks {
+        self.payload() as *mut FreeLinks
+    }
+
+    fn from_payload(ptr: *mut u8) -> *mut BlockHeader {
+        (ptr as usize - HEADER_SIZE) as *mut BlockHeader
+    }
+}
+
+/// Two-level segregated fit allocator.
+///
+/// Free blocks are kept in lists by size class, and two bitmaps tell which
+/// lists are non-empty, so a fitting block is found without searching. Freed
+/// blocks are merged with free neighbours right away. Allocating and freeing
+/// take constant time, apart from growing the heap.
+pub struct TlsfAllocator {
+    // bit i set if any list of first level i is non-empty
+    fl_bitmap: u64,
+    // bit j of sl_bitmaps[i] set if free_lists[i][j] is non-empty
+    sl_bitmaps: [u32; FL_COUNT],
+    free_lists: [[*mut BlockHeader; SL_COUNT]; FL_COUNT],
+    heap_start: usize,
+    heap_end: usize,
+    grow: Option<GrowFn>,
+}
+
+// the raw pointers only point into the heap owned by the allocator
+unsafe impl Send for TlsfAllocator {}
+
+impl TlsfAllocator {
+    pub const fn new() -> Self {
+        TlsfAllocator {
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            free_lists: [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+            heap_start: 0,
+            heap_end: 0,
+            grow: None,
+        }
+    }
+
+    /// Lets the heap grow through `grow` once it is exhausted.
+    pub fn set_grow_fn(&mut self, grow: GrowFn) {
+        self.grow = Some(grow);
+    }
+
+    /// Initializes the allocator with the given heap bounds.
+    ///
+    /// Unsafe because the caller must guarantee that the memory range is unused.
+    /// Must only be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let start = align_up(heap_start, ALIGN);
+        let end = (heap_start + heap_size) & !(ALIGN - 1);
+        assert!(
+            start + 2 * HEADER_SIZE + MIN_BLOCK_SIZE <= end,
+            "heap too small"
+        );
+        self.heap_start = start;
+        self.heap_end = end;
+
+        // one free block over the whole heap, followed by a used sentinel block
+        // of size 0, so the last block never merges past the heap end
+        let block = start as *mut BlockHeader;
+        block.write(BlockHeader {
+            prev_phys: ptr::null_mut(),
+            size: end - start - 2 * HEADER_SIZE,
+        });
+        ((*block).next_phys()).write(BlockHeader {
+            prev_phys: block,
+            size: 0,
+        });
+        self.insert_free(block);
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = align_up(layout.size().max(MIN_BLOCK_SIZE), ALIGN);
+        // blocks are only ALIGN aligned, larger alignments need room to move
+        // the start of the block forward
+        let search_size = if layout.align() > ALIGN {
+            match size.checked_add(2 * layout.align()) {
+                Some(search_size) => search_size,
+                None => return ptr::null_mut(),
+            }
+        } else {
+            size
+        };
+
+        let mut found = self.find_free(search_size);
+        if found.is_none() {
+            if let Some(rounded) = round_up_size(search_size) {
+                if self.grow(rounded + HEADER_SIZE) {
+                    found = self.find_free(search_size);
+                }
+            }
+        }
+        let mut block = match found {
+            Some(block) => block,
+            None => return ptr::null_mut(), // out of memory
+        };
+
+        self.remove_free(block);
+        if layout.align() > ALIGN {
+            block = self.split_front(block, layout.align());
+        }
+        self.split_back(block, size);
+        (*block).payload()
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, _layout: Layout) {
+        let block = BlockHeader::from_payload(ptr);
+        debug_assert!(!(*block).is_free(), "double free");
+        self.release(block);
+    }
+
+    /// Resizes an allocation without moving it, returns false if that's not possible.
+    ///
+    /// Growing takes space from the block directly behind the allocation if that
+    /// one is free.
+    pub unsafe fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        _layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let new_size = align_up(new_size.max(MIN_BLOCK_SIZE), ALIGN);
+        let block = BlockHeader::from_payload(ptr);
+        let size = (*block).size();
+
+        if new_size > size {
+            let next = (*block).next_phys();
+            if !(*next).is_free() || size + HEADER_SIZE + (*next).size() < new_size {
+                return false;
+            }
+            self.remove_free(next);
+            (*block).set_size(size + HEADER_SIZE + (*next).size());
+            (*(*block).next_phys()).prev_phys = block;
+        }
+        self.split_back(block, new_size);
+        true
+    }
+
+    // map at least `min_size` more bytes above the heap and add them as free block
+    fn grow(&mut self, min_size: usize) -> bool {
+        let grown = match super::grow(self.grow, self.heap_end, min_size) {
+            Some(grown) => grown,
+            None => return false,
+        };
+
+        unsafe {
+            // the old sentinel becomes the header of the new block
+            let block = (self.heap_end - HEADER_SIZE) as *mut BlockHeader;
+            (*block).set_size(grown - HEADER_SIZE);
+            ((*block).next_phys()).write(BlockHeader {
+                prev_phys: block,
+                size: 0,
+            });
+            self.heap_end += grown;
+            self.release(block);
+        }
+        true
+    }
+
+    // returns a free block of at least `size` bytes, without removing it
+    fn find_free(&self, size: usize) -> Option<*mut BlockHeader> {
+        // round up to the next list, so every block in the found list fits
+        let (fl, sl) = mapping(round_up_size(size)?);
+        if fl >= FL_COUNT {
+            return None;
+        }
+
+        let sl_map = self.sl_bitmaps[fl] & (!0 << sl);
+        let (fl, sl) = if sl_map != 0 {
+            (fl, sl_map.trailing_zeros() as usize)
+        } else {
+            // nothing left in this first level, take the next larger one
+            let fl_map = self.fl_bitmap & (!0 << (fl + 1));
+            if fl_map == 0 {
+                return None;
+            }
+            let fl = fl_map.trailing_zeros() as usize;
+            (fl, self.sl_bitmaps[fl].trailing_zeros() as usize)
+        };
+        Some(self.free_lists[fl][sl])
+    }
+
+    unsafe fn insert_free(&mut self, block: *mut BlockHeader) {
+        let (fl, sl) = mapping((*block).size());
+        debug_assert!(fl < FL_COUNT, "block too large");
+        let head = self.free_lists[fl][sl];
+        (*block).links().write(FreeLinks {
+            next: head,
+            prev: ptr::null_mut(),
+        });
+        if !head.is_null() {
+            (*(*head).links()).prev = block;
+        }
+        self.free_lists[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmaps[fl] |= 1 << sl;
+        (*block).size |= FREE;
+    }
+
+    unsafe fn remove_free(&mut self, block: *mut BlockHeader) {
+        let (fl, sl) = mapping((*block).size());
+        let links = (*block).links().read();
+        if links.prev.is_null() {
+            self.free_lists[fl][sl] = links.next;
+        } else {
+            (*(*links.prev).links()).next = links.next;
+        }
+        if !links.next.is_null() {
+            (*(*links.next).links()).prev = links.prev;
+        }
+
+        if self.free_lists[fl][sl].is_null() {
+            self.sl_bitmaps[fl] &= !(1 << sl);
+            if self.sl_bitmaps[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+        (*block).size &= !FREE;
+    }
+
+    // merge a block that is no longer used with its free neighbours and put it
+    // on its free list
+    unsafe fn release(&mut self, mut block: *mut BlockHeader) {
+        let next = (*block).next_phys();
+        if (*next).is_free() {
+            self.remove_free(next);
+            (*block).set_size((*block).size() + HEADER_SIZE + (*next).size());
+            (*(*block).next_phys()).prev_phys = block;
+        }
+
+        let prev = (*block).prev_phys;
+        if !prev.is_null() && (*prev).is_free() {
+            self.remove_free(prev);
+            (*prev).set_size((*prev).size() + HEADER_SIZE + (*block).size());
+            (*(*prev).next_phys()).prev_phys = prev;
+            block = prev;
+        }
+
+        self.insert_free(block);
+    }
+
+    // split off the start of a block so its payload is aligned to `align`
+    // the part in front goes back on a free list, returns the aligned block
+    unsafe fn split_front(&mut self, block: *mut BlockHeader, align: usize) -> *mut BlockHeader {
+        let payload = (*block).payload() as usize;
+        let mut gap = align_up(payload, align) - payload;
+        if gap == 0 {
+            return block;
+        }
+        // the part in front must be able to hold a free block
+        if gap < HEADER_SIZE + MIN_BLOCK_SIZE {
+            gap += align;
+        }
+
+        let aligned = (payload + gap - HEADER_SIZE) as *mut BlockHeader;
+        aligned.write(BlockHeader {
+            prev_phys: block,
+            size: (*block).size() - gap,
+        });
+        (*(*aligned).next_phys()).prev_phys = aligned;
+        (*block).set_size(gap - HEADER_SIZE);
+        // the block in front of it is used, free blocks never touch
+        self.insert_free(block);
+        aligned
+    }
+
+    // cut a block down to `size` bytes, if the rest is large enough for a block
+    unsafe fn split_back(&mut self, block: *mut BlockHeader, size: usize) {
+        let block_size = (*block).size();
+        if block_size < size + HEADER_SIZE + MIN_BLOCK_SIZE {
+            return;
+        }
+
+        let rest = ((*block).payload() as usize + size) as *mut BlockHeader;
+        rest.write(BlockHeader {
+            prev_phys: block,
+            size: block_size - size - HEADER_SIZE,
+        });
+        (*(*rest).next_phys()).prev_phys = rest;
+        (*block).set_size(size);
+        self.release(rest);
+    }
+}
+
+// list of a block size as (first level, second level) index
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        (0, size / (SMALL_BLOCK / SL_COUNT))
+    } else {
+        let log2 = (usize::BITS - 1 - size.leading_zeros()) as usize;
+        let fl = log2 - FL_SHIFT + 1;
+        let sl = (size >> (log2 - SL_LOG2)) - SL_COUNT;
+        (fl, sl)
+    }
+}
+
+// smallest size whose list only holds blocks of at least `size` bytes
+fn round_up_size(size: usize) -> Option<usize> {
+    if size < SMALL_BLOCK {
+        return Some(size);
+    }
+    let log2 = (usize::BITS - 1 - size.leading_zeros()) as usize;
+    let round = (1 << (log2 - SL_LOG2)) - 1;
+    size.checked_add(round).map(|size| size & !round)
+}
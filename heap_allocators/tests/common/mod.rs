@@ -0,0 +1,30 @@
+// shared by the test files, each only uses part of it
+#![allow(dead_code)]
+
+use std::alloc::{alloc, dealloc, Layout};
+
+// page aligned memory from the host allocator
+pub struct Memory {
+    start: *mut u8,
+    layout: Layout,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, 4096).unwrap();
+        let start = unsafe { alloc(layout) };
+        assert!(!start.is_null());
+        Memory { start, layout }
+    }
+
+    pub fn range(&self) -> (usize, usize) {
+        let start = self.start as usize;
+        (start, start + self.layout.size())
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start, self.layout) };
+    }
+}
@@ -0,0 +1,375 @@
+// Random allocation sequences against every allocator, on a plain buffer
+// Checks that allocations are aligned, inside the heap and never overlap, and
+// that all memory can be allocated again once everything is freed
+
+mod common;
+
+use common::Memory;
+use heap_allocators::bump::BumpAllocator;
+use heap_allocators::fixed_size_block::FixedSizeBlockAllocator;
+use heap_allocators::linked_list::{FitPolicy, LinkedListAllocator};
+use heap_allocators::tlsf::TlsfAllocator;
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const HEAP_SIZE: usize = 256 * 1024;
+const SEEDS: u64 = 64;
+const OPERATIONS: usize = 4000;
+
+// common interface of the allocators under test
+trait TestHeap {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8;
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+    unsafe fn resize_in_place(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool;
+}
+
+macro_rules! test_heap {
+    ($($allocator:ty),*) => {$(
+        impl TestHeap for $allocator {
+            unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+                <$allocator>::init(self, heap_start, heap_size)
+            }
+
+            unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+                <$allocator>::allocate(self, layout)
+            }
+
+            unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+                <$allocator>::deallocate(self, ptr, layout)
+            }
+
+            unsafe fn resize_in_place(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+                <$allocator>::resize_in_place(self, ptr, layout, new_size)
+            }
+        }
+    )*};
+}
+
+test_heap!(
+    BumpAllocator,
+    LinkedListAllocator,
+    FixedSizeBlockAllocator,
+    TlsfAllocator
+);
+
+// xorshift, reproducible from the seed
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    fn layout(&mut self) -> Layout {
+        // mostly small objects, now and then a large one
+        let size = match self.next(10) {
+            0 => 1 + self.next(8 * 1024),
+            1..=3 => 1 + self.next(512),
+            _ => 1 + self.next(64),
+        };
+        let align = match self.next(20) {
+            0 => 4096,
+            1..=3 => 1 << self.next(8),
+            _ => 8,
+        };
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+struct Allocation {
+    layout: Layout,
+    fill: u8,
+}
+
+// live allocations by address
+#[derive(Default)]
+struct Live {
+    allocations: BTreeMap<usize, Allocation>,
+    bytes: usize,
+}
+
+impl Live {
+    fn insert(&mut self, ptr: *mut u8, layout: Layout, fill: u8, heap: (usize, usize)) {
+        let start = ptr as usize;
+        let end = start + layout.size();
+        assert_eq!(
+            start % layout.align(),
+            0,
+            "{:?} misaligned at {:#x}",
+            layout,
+            start
+        );
+        assert!(
+            heap.0 <= start && end <= heap.1,
+            "{:#x} outside of the heap",
+            start
+        );
+        self.check_free(start, end);
+
+        unsafe { ptr.write_bytes(fill, layout.size()) };
+        self.allocations.insert(start, Allocation { layout, fill });
+        self.bytes += layout.size();
+    }
+
+    // panics if any live allocation overlaps start..end
+    fn check_free(&self, start: usize, end: usize) {
+        if let Some((&before, allocation)) = self.allocations.range(..end).next_back() {
+            let before_end = before + allocation.layout.size();
+            assert!(
+                before_end <= start,
+                "{:#x}..{:#x} overlaps {:#x}",
+                start,
+                end,
+                before
+            );
+        }
+    }
+
+    fn remove(&mut self, start: usize) -> Allocation {
+        let allocation = self.allocations.remove(&start).unwrap();
+        let bytes =
+            unsafe { std::slice::from_raw_parts(start as *const u8, allocation.layout.size()) };
+        assert!(
+            bytes.iter().all(|&b| b == allocation.fill),
+            "allocation at {:#x} was overwritten",
+            start
+        );
+        self.bytes -= allocation.layout.size();
+        allocation
+    }
+
+    fn pick(&self, random: &mut Random) -> usize {
+        *self
+            .allocations
+            .keys()
+            .nth(random.next(self.allocations.len()))
+            .unwrap()
+    }
+}
+
+// runs random allocations, frees and resizes, then frees everything and
+// allocates `reclaimable` bytes in one go
+// returns the number of failed allocations
+fn run<A: TestHeap>(allocator: &mut A, seed: u64, reclaimable: usize) -> usize {
+    let memory = Memory::new(HEAP_SIZE);
+    let heap = memory.range();
+    unsafe { allocator.init(heap.0, HEAP_SIZE) };
+
+    let mut random = Random::new(seed);
+    let mut live = Live::default();
+    let mut failed = 0;
+
+    for operation in 0..OPERATIONS {
+        let fill = operation as u8;
+        match random.next(8) {
+            // keep the heap at most a quarter full, so allocations can't fail
+            // because of fragmentation
+            0..=3 if live.bytes < HEAP_SIZE / 4 => {
+                let layout = random.layout();
+                let ptr = unsafe { allocator.allocate(layout) };
+                if ptr.is_null() {
+                    failed += 1;
+                } else {
+                    live.insert(ptr, layout, fill, heap);
+                }
+            }
+            6 if !live.allocations.is_empty() => {
+                let start = live.pick(&mut random);
+                let old = live.remove(start);
+                let new_size = 1 + random.next(2 * old.layout.size());
+                let ptr = start as *mut u8;
+                if unsafe { allocator.resize_in_place(ptr, old.layout, new_size) } {
+                    let layout = Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                    live.insert(ptr, layout, fill, heap);
+                } else {
+                    live.insert(ptr, old.layout, fill, heap);
+                }
+            }
+            _ if !live.allocations.is_empty() => {
+                let start = live.pick(&mut random);
+                let allocation = live.remove(start);
+                unsafe { allocator.deallocate(start as *mut u8, allocation.layout) };
+            }
+            _ => {}
+        }
+    }
+
+    while let Some((&start, _)) = live.allocations.iter().next() {
+        let allocation = live.remove(start);
+        unsafe { allocator.deallocate(start as *mut u8, allocation.layout) };
+    }
+
+    let layout = Layout::from_size_align(reclaimable, 8).unwrap();
+    let ptr = unsafe { allocator.allocate(layout) };
+    assert!(!ptr.is_null(), "memory not reclaimed with seed {}", seed);
+    live.insert(ptr, layout, 0, heap);
+    failed
+}
+
+#[test]
+fn bump() {
+    for seed in 0..SEEDS {
+        // only reuses memory once everything is freed, so it may run out
+        run(&mut BumpAllocator::new(), seed, HEAP_SIZE);
+    }
+}
+
+#[test]
+fn linked_list_first_fit() {
+    for seed in 0..SEEDS {
+        let mut allocator = LinkedListAllocator::with_policy(FitPolicy::FirstFit);
+        assert_eq!(run(&mut allocator, seed, HEAP_SIZE), 0);
+    }
+}
+
+#[test]
+fn linked_list_best_fit() {
+    for seed in 0..SEEDS {
+        let mut allocator = LinkedListAllocator::with_policy(FitPolicy::BestFit);
+        assert_eq!(run(&mut allocator, seed, HEAP_SIZE), 0);
+    }
+}
+
+#[test]
+fn fixed_size_block() {
+    for seed in 0..SEEDS {
+        // the free lists go back to the fallback allocator when it runs out
+        let reclaimable = HEAP_SIZE - 4096;
+        let failed = run(&mut FixedSizeBlockAllocator::new(), seed, reclaimable);
+        // headers and red zones take up more than the quarter of the heap in use
+        if !cfg!(feature = "heap-debug") {
+            assert_eq!(failed, 0);
+        }
+    }
+}
+
+#[test]
+fn tlsf() {
+    for seed in 0..SEEDS {
+        // the largest size class below the heap size
+        let reclaimable = HEAP_SIZE / 16 * 15;
+        assert_eq!(run(&mut TlsfAllocator::new(), seed, reclaimable), 0);
+    }
+}
+
+#[test]
+fn allocators_without_grow_fn_stay_in_place() {
+    let mut allocator = LinkedListAllocator::new();
+    let memory = Memory::new(HEAP_SIZE);
+    unsafe { allocator.init(memory.range().0, HEAP_SIZE) };
+    let layout = Layout::from_size_align(2 * HEAP_SIZE, 8).unwrap();
+    assert!(unsafe { allocator.allocate(layout) }.is_null());
+    assert_eq!(allocator.heap_size(), HEAP_SIZE);
+}
+
+#[test]
+fn grow_fn_extends_the_heap() {
+    // the heap starts in the first quarter of the buffer and may grow over the rest
+    static BUFFER_END: AtomicUsize = AtomicUsize::new(0);
+    fn grow(heap_top: usize, min_size: usize) -> Option<usize> {
+        let buffer_end = BUFFER_END.load(Ordering::Relaxed);
+        (heap_top + min_size <= buffer_end).then(|| buffer_end - heap_top)
+    }
+
+    let memory = Memory::new(HEAP_SIZE);
+    let (start, end) = memory.range();
+    BUFFER_END.store(end, Ordering::Relaxed);
+
+    let mut allocator = TlsfAllocator::new();
+    allocator.set_grow_fn(grow);
+    unsafe { allocator.init(start, HEAP_SIZE / 4) };
+    let layout = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+    assert!(!unsafe { allocator.allocate(layout) }.is_null());
+    assert_eq!(allocator.heap_size(), HEAP_SIZE);
+}
+
+// fills a quarter of the heap with random small allocations, then frees every other one
+// returns the largest allocation that still fits between the survivors
+fn largest_after_fragmentation<A: TestHeap>(allocator: &mut A, seed: u64) -> usize {
+    let memory = Memory::new(HEAP_SIZE);
+    unsafe { allocator.init(memory.range().0, HEAP_SIZE) };
+
+    let mut random = Random::new(seed);
+    let mut allocations = Vec::new();
+    let mut bytes = 0;
+    while bytes < HEAP_SIZE / 4 {
+        let layout = Layout::from_size_align(1 + random.next(512), 8).unwrap();
+        let ptr = unsafe { allocator.allocate(layout) };
+        assert!(!ptr.is_null());
+        allocations.push((ptr, layout));
+        bytes += layout.size();
+    }
+    for &(ptr, layout) in allocations.iter().skip(1).step_by(2) {
+        unsafe { allocator.deallocate(ptr, layout) };
+    }
+
+    // a larger allocation never fits where a smaller one doesn't
+    let (mut low, mut high) = (0, HEAP_SIZE);
+    while low < high {
+        let size = (low + high + 1) / 2;
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { allocator.allocate(layout) };
+        if ptr.is_null() {
+            high = size - 1;
+        } else {
+            unsafe { allocator.deallocate(ptr, layout) };
+            low = size;
+        }
+    }
+    low
+}
+
+// the largest allocation possible in a fragmented heap, compared between allocators
+#[test]
+fn fragmentation() {
+    for seed in 0..SEEDS {
+        let tlsf = largest_after_fragmentation(&mut TlsfAllocator::new(), seed);
+        let first_fit = largest_after_fragmentation(
+            &mut LinkedListAllocator::with_policy(FitPolicy::FirstFit),
+            seed,
+        );
+        let fixed = largest_after_fragmentation(&mut FixedSizeBlockAllocator::new(), seed);
+        // TLSF rounds a request up to the next size class, which costs at most
+        // two of the 16 classes between powers of two
+        assert!(
+            tlsf >= first_fit - first_fit / 8,
+            "tlsf {} first fit {} with seed {}",
+            tlsf,
+            first_fit,
+            seed
+        );
+        // freed blocks stay in the block lists, large allocations can only use
+        // what's left in the fallback heap
+        assert!(
+            tlsf > fixed,
+            "tlsf {} fixed size block {} with seed {}",
+            tlsf,
+            fixed,
+            seed
+        );
+    }
+}
+
+#[test]
+fn bump_reset_frees_everything() {
+    let mut allocator = BumpAllocator::new();
+    let memory = Memory::new(HEAP_SIZE);
+    unsafe { allocator.init(memory.range().0, HEAP_SIZE) };
+    let layout = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+    let first = unsafe { allocator.allocate(layout) };
+    assert!(!unsafe { allocator.allocate(layout) }.is_null());
+    assert_eq!(allocator.used(), HEAP_SIZE);
+
+    unsafe { allocator.reset() };
+    assert_eq!(allocator.used(), 0);
+    assert_eq!(unsafe { allocator.allocate(layout) }, first);
+}
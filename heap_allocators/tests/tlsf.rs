@@ -0,0 +1,57 @@
+// TLSF specific behaviour, the random sequences in random.rs cover the rest
+
+mod common;
+
+use common::Memory;
+use heap_allocators::tlsf::TlsfAllocator;
+use std::alloc::Layout;
+
+const HEAP_SIZE: usize = 64 * 1024;
+
+fn tlsf(memory: &Memory) -> TlsfAllocator {
+    let mut allocator = TlsfAllocator::new();
+    unsafe { allocator.init(memory.range().0, HEAP_SIZE) };
+    allocator
+}
+
+#[test]
+fn freed_block_is_reused() {
+    let memory = Memory::new(HEAP_SIZE);
+    let mut allocator = tlsf(&memory);
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    unsafe {
+        let first = allocator.allocate(layout);
+        allocator.allocate(layout);
+        allocator.deallocate(first, layout);
+        assert_eq!(allocator.allocate(layout), first);
+    }
+}
+
+#[test]
+fn large_alignment() {
+    let memory = Memory::new(HEAP_SIZE);
+    let mut allocator = tlsf(&memory);
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let aligned = Layout::from_size_align(64, 4096).unwrap();
+    unsafe {
+        allocator.allocate(small);
+        let ptr = allocator.allocate(aligned);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 4096, 0);
+        allocator.deallocate(ptr, aligned);
+    }
+}
+
+#[test]
+fn resize_grows_into_free_neighbour() {
+    let memory = Memory::new(HEAP_SIZE);
+    let mut allocator = tlsf(&memory);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = allocator.allocate(layout);
+        let neighbour = allocator.allocate(layout);
+        allocator.allocate(layout);
+        allocator.deallocate(neighbour, layout);
+        assert!(allocator.resize_in_place(ptr, layout, 128));
+    }
+}
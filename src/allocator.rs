@@ -1,23 +1,71 @@
+use crate::memory::{
+    self,
+    region::{self, RegionKind},
+};
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::ptr::null_mut;
-use fixed_size_block::FixedSizeBlockAllocator;
-use x86_64::{
-    structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
-    },
-    VirtAddr,
+use core::ptr::{self, null_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use heap_allocators::align_up;
+use stats::HeapStats;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, PageSize, PageTableFlags,
+    Size2MiB, Size4KiB,
 };
 
+pub mod arena;
 pub mod bump;
 pub mod fixed_size_block;
+pub mod leak;
 pub mod linked_list;
+pub mod oom;
+pub mod slab;
+pub mod stats;
+pub mod tlsf;
+
+#[cfg(feature = "heap-debug")]
+pub use heap_allocators::debug;
 pub struct Dummy;
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+// picked from the region manager's dynamic window by init_heap
+static HEAP_START: AtomicUsize = AtomicUsize::new(0);
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, mapped up front
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB, rest is mapped on first access
+const HEAP_GROWTH: usize = 64 * 1024; // heap grows by at least 64 KiB at a time
+
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-tlsf",
+    feature = "alloc-fixed-block"
+)))]
+compile_error!("select the global allocator with one of the `alloc-*` features");
+
+// selecting another allocator needs --no-default-features, otherwise the
+// default fixed size block allocator is enabled as well
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-tlsf"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-tlsf"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-block"),
+    all(feature = "alloc-tlsf", feature = "alloc-fixed-block")
+))]
+compile_error!(
+    "only one `alloc-*` feature may be enabled, use --no-default-features to pick another one"
+);
+
+// the allocator backing the heap, chosen by cargo feature
+#[cfg(feature = "alloc-bump")]
+type GlobalHeap = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+type GlobalHeap = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-tlsf")]
+type GlobalHeap = tlsf::TlsfAllocator;
+#[cfg(feature = "alloc-fixed-block")]
+type GlobalHeap = fixed_size_block::FixedSizeBlockAllocator;
 
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: Locked<GlobalHeap> = Locked::with_oom_hooks(GlobalHeap::new());
 
 unsafe impl GlobalAlloc for Dummy {
     unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
@@ -29,63 +77,179 @@ unsafe impl GlobalAlloc for Dummy {
     }
 }
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        // ? makes it return when allocate_frame returns None
-        // ? forwards error to caller
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        // flush() update translation lookaside buffer
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
-    }
+// Maps the first HEAP_SIZE bytes with 2 MiB pages where the size allows, 4 KiB pages otherwise
+// The rest up to HEAP_MAX_SIZE is demand paged, which needs memory::init_kernel_memory
+// to be called before the heap grows past HEAP_SIZE
+pub fn init_heap<M, A>(mapper: &mut M, frame_allocator: &mut A) -> Result<(), MapToError<Size4KiB>>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB>,
+    A: FrameAllocator<Size4KiB>
+        + FrameAllocator<Size2MiB>
+        + FrameDeallocator<Size4KiB>
+        + FrameDeallocator<Size2MiB>,
+{
+    // 2 MiB aligned, so the heap can use huge pages
+    let heap_start = region::allocate(HEAP_MAX_SIZE as u64, Size2MiB::SIZE, RegionKind::Heap)
+        .expect("no address space left for the heap")
+        .start;
+    HEAP_START.store(heap_start.as_u64() as usize, Ordering::Relaxed);
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
-    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    // ? forwards error to caller
+    memory::map_region(heap_start, HEAP_SIZE as u64, flags, mapper, frame_allocator)?;
+    memory::lazy::register(heap_start, HEAP_MAX_SIZE as u64, flags)
+        .expect("heap overlaps another lazy region");
+
+    let mut allocator = ALLOCATOR.lock();
+    unsafe { allocator.init(heap_start.as_u64() as usize, HEAP_SIZE) };
+    allocator.set_grow_fn(grow_heap);
 
     Ok(())
 }
 
+/// Start of the heap's virtual address range, 0 before `init_heap`.
+pub fn heap_start() -> usize {
+    HEAP_START.load(Ordering::Relaxed)
+}
+
+/// Allocation counts of the kernel heap.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+/// Allocations per block size of the kernel heap.
+#[cfg(feature = "alloc-fixed-block")]
+pub fn block_stats() -> fixed_size_block::BlockStats {
+    ALLOCATOR.lock().block_stats()
+}
+
+/// Current size of the heap in bytes, including the parts it has grown by.
+pub fn heap_size() -> usize {
+    ALLOCATOR.lock().heap_size()
+}
+
+// Extends the heap by at least `min_size` bytes directly above `heap_top`
+// returns the number of bytes added, None if the heap can't grow
+// nothing is mapped here, the heap region is demand paged (see init_heap), so
+// the page fault handler maps the new pages once the allocator touches them
+// called with the allocator locked, so it must not allocate itself
+fn grow_heap(heap_top: usize, min_size: usize) -> Option<usize> {
+    let heap_end = heap_start() + HEAP_MAX_SIZE;
+    // never grow outside of the heap region
+    if heap_top < heap_start() || heap_top > heap_end {
+        return None;
+    }
+    let size = align_up(min_size.max(HEAP_GROWTH), 4096).min(heap_end - heap_top);
+    if size < min_size {
+        return None;
+    }
+
+    // only grow as far as there are frames to back the new pages, so running
+    // out of memory fails the allocation instead of the page fault
+    let free_frames =
+        memory::try_with_kernel_memory(|memory| memory.frame_allocator.free_frames())?;
+    if free_frames * 4096 < size {
+        return None;
+    }
+    Some(size)
+}
+
 // Wrapper around mutex to allow trait implementations
+// also counts the allocations going through it
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
+    stats: stats::Counters,
+    oom_hooks: bool,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
             inner: spin::Mutex::new(inner),
+            stats: stats::Counters::new(),
+            oom_hooks: false,
+        }
+    }
+
+    /// Like `new`, but failing allocations run the OOM hooks before giving up.
+    ///
+    /// The hooks free memory of the global heap, so this is only useful for
+    /// the global allocator and tests standing in for it.
+    pub const fn with_oom_hooks(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+            stats: stats::Counters::new(),
+            oom_hooks: true,
         }
     }
 
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
-}
 
-// Align given address 'addr' upwards to alignment 'align'
-fn align_up(addr: usize, align: usize) -> usize {
-    /* let remainder = addr & align;
-    if remainder == 0 {
-        addr // already aligned
-    } else {
-        addr - remainder + align
-    } */
-
-    // more efficient way
-    // align must be power of 2
-    (addr + align - 1) & !(align - 1)
+    pub fn stats(&self) -> HeapStats {
+        self.stats.snapshot()
+    }
+
+    // shared alloc of the GlobalAlloc impls
+    // if `allocate` fails and the OOM hooks are enabled, they get to free
+    // memory before it's retried
+    unsafe fn alloc_with(
+        &self,
+        layout: Layout,
+        allocate: unsafe fn(&mut A, Layout) -> *mut u8,
+    ) -> *mut u8 {
+        let mut ptr = allocate(&mut self.lock(), layout);
+        if ptr.is_null() && self.oom_hooks {
+            // not locked anymore, the hooks free memory through this allocator
+            ptr = oom::retry(layout, || allocate(&mut self.lock(), layout));
+        }
+        self.record_alloc(ptr, layout);
+        ptr
+    }
+
+    // called after allocating, without the lock held
+    fn record_alloc(&self, ptr: *mut u8, layout: Layout) {
+        if ptr.is_null() {
+            self.stats.record_failure();
+        } else {
+            self.stats.record_alloc(layout.size());
+            leak::record_alloc(ptr as usize, layout.size());
+        }
+    }
+
+    // called by the GlobalAlloc impls before deallocating, so the address can't
+    // be handed out again before its record is gone
+    fn record_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.stats.record_dealloc(layout.size());
+        leak::record_dealloc(ptr as usize);
+    }
+
+    // shared realloc of the GlobalAlloc impls
+    // `resize_in_place` gets the allocator locked and returns true if it could
+    // resize the allocation without moving it, otherwise it's moved to a new one
+    unsafe fn realloc_with(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+        resize_in_place: unsafe fn(&mut A, *mut u8, Layout, usize) -> bool,
+    ) -> *mut u8
+    where
+        Self: GlobalAlloc,
+    {
+        if resize_in_place(&mut self.lock(), ptr, layout, new_size) {
+            self.stats.record_resize(layout.size(), new_size);
+            leak::record_resize(ptr as usize, new_size);
+            return ptr;
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
 }
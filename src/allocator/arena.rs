@@ -0,0 +1,153 @@
+use super::bump::BumpAllocator;
+use crate::memory::region::{self, RegionError, RegionKind};
+use crate::memory::{self, with_kernel_memory};
+use core::alloc::{AllocError, Allocator, Layout};
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+#[derive(Debug)]
+pub enum ArenaError {
+    /// Kernel memory wasn't handed over via `init_kernel_memory` yet.
+    NotInitialized,
+    Region(RegionError),
+    Map(MapToError<Size4KiB>),
+}
+
+/// Scratch memory in a region of its own, handed out by a bump allocator.
+///
+/// Allocate from it through the `Allocator` API, e.g. with `Vec::new_in(&arena)`.
+/// Everything is freed at once by `reset` or by dropping the arena, which
+/// unmaps the region.
+pub struct Arena {
+    start: VirtAddr,
+    size: u64,
+    bump: Mutex<BumpAllocator>,
+}
+
+impl Arena {
+    /// Maps an arena of at least `size` bytes, rounded up to whole pages.
+    pub fn new(size: usize) -> Result<Arena, ArenaError> {
+        let size = (size as u64 + Size4KiB::SIZE - 1) & !(Size4KiB::SIZE - 1);
+        let region = region::allocate(size, Size4KiB::SIZE, RegionKind::Arena)
+            .map_err(ArenaError::Region)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        let result = with_kernel_memory(|memory| {
+            let mapper = &mut memory.mapper;
+            let frame_allocator = &mut memory.frame_allocator;
+            memory::map_region(region.start, size, flags, mapper, frame_allocator).map_err(|err| {
+                // undo the pages mapped so far
+                memory::unmap_region(region.start, size, mapper, frame_allocator).ok();
+                ArenaError::Map(err)
+            })
+        })
+        .unwrap_or(Err(ArenaError::NotInitialized));
+
+        if let Err(err) = result {
+            region::release(region.start).ok();
+            return Err(err);
+        }
+
+        let mut bump = BumpAllocator::new();
+        unsafe { bump.init(region.start.as_u64() as usize, size as usize) };
+        Ok(Arena {
+            start: region.start,
+            size,
+            bump: Mutex::new(bump),
+        })
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Bytes handed out since the last reset, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.bump.lock().used()
+    }
+
+    /// Frees everything allocated from the arena.
+    ///
+    /// Safe because allocations borrow the arena, so none can be alive here.
+    pub fn reset(&mut self) {
+        unsafe { self.bump.lock().reset() };
+    }
+
+    fn slice(ptr: *mut u8, size: usize) -> Result<NonNull<[u8]>, AllocError> {
+        NonNull::new(ptr::slice_from_raw_parts_mut(ptr, size)).ok_or(AllocError)
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = unsafe { self.bump.lock().allocate(layout) };
+        Arena::slice(ptr, layout.size())
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.bump.lock().deallocate(ptr.as_ptr(), layout);
+    }
+
+    // the most recent allocation grows in place, so a growing Vec doesn't waste
+    // the arena with copies of itself
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let aligned = ptr.as_ptr() as usize % new_layout.align() == 0;
+        let resized = aligned
+            && self
+                .bump
+                .lock()
+                .resize_in_place(ptr.as_ptr(), old_layout, new_layout.size());
+        if resized {
+            return Arena::slice(ptr.as_ptr(), new_layout.size());
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let aligned = ptr.as_ptr() as usize % new_layout.align() == 0;
+        if aligned {
+            // shrinking a bump allocation always works in place
+            self.bump
+                .lock()
+                .resize_in_place(ptr.as_ptr(), old_layout, new_layout.size());
+            return Arena::slice(ptr.as_ptr(), new_layout.size());
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, new_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        with_kernel_memory(|memory| {
+            let mapper = &mut memory.mapper;
+            let frame_allocator = &mut memory.frame_allocator;
+            memory::unmap_region(self.start, self.size, mapper, frame_allocator).ok();
+        });
+        region::release(self.start).ok();
+    }
+}
@@ -1,61 +1,19 @@
-use super::{align_up, Locked};
+use super::Locked;
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::ptr;
 
-pub struct BumpAllocator {
-    heap_start: usize,
-    heap_end: usize,
-    next: usize,
-    allocations: usize,
-}
-
-impl BumpAllocator {
-    // create empty bump allocator
-    pub const fn new() -> Self {
-        BumpAllocator {
-            heap_start: 0,
-            heap_end: 0,
-            next: 0,
-            allocations: 0,
-        }
-    }
-
-    // Initialize bump allocator within bounds
-    // Unsafe: caller must ensure given mem range is unused
-    // Must only be called once
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.heap_start = heap_start;
-        self.heap_end = heap_start + heap_size;
-        self.next = heap_start;
-    }
-}
+pub use heap_allocators::bump::*;
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump = self.lock();
-
-        // round up 'next' address to alignment specified by layout
-        let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(), // out of memory
-        };
-
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
-        }
+        self.alloc_with(layout, BumpAllocator::allocate)
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut bump = self.lock(); // get mutable reference
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.record_dealloc(ptr, layout);
+        self.lock().deallocate(ptr, layout);
+    }
 
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
-        }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc_with(ptr, layout, new_size, BumpAllocator::resize_in_place)
     }
 }
@@ -0,0 +1,151 @@
+use crate::println;
+use spin::Mutex;
+
+const MAX_RECORDS: usize = 512;
+
+/// Tag naming the current source location, for use with `set_tag`/`with_tag`.
+#[macro_export]
+macro_rules! alloc_tag {
+    () => {
+        concat!(file!(), ":", line!())
+    };
+}
+
+/// A live allocation recorded while leak tracking was enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationRecord {
+    pub addr: usize,
+    pub size: usize,
+    /// Tag that was set when the allocation was made.
+    pub tag: &'static str,
+    checkpoint: u64,
+}
+
+/// Marks a point in time, see `outstanding_since`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(u64);
+
+// fixed size table, the tracker runs inside the allocator and can't allocate itself
+struct LeakTracker {
+    enabled: bool,
+    tag: &'static str,
+    checkpoint: u64,
+    records: [Option<AllocationRecord>; MAX_RECORDS],
+    // allocations not recorded because the table was full
+    dropped: usize,
+}
+
+static TRACKER: Mutex<LeakTracker> = Mutex::new(LeakTracker {
+    enabled: false,
+    tag: "untagged",
+    checkpoint: 0,
+    records: [None; MAX_RECORDS],
+    dropped: 0,
+});
+
+/// Starts recording every allocation made through a `Locked` allocator.
+pub fn enable() {
+    TRACKER.lock().enabled = true;
+}
+
+/// Stops recording and forgets all records.
+pub fn disable() {
+    let mut tracker = TRACKER.lock();
+    tracker.enabled = false;
+    tracker.records = [None; MAX_RECORDS];
+    tracker.dropped = 0;
+}
+
+/// Sets the tag attached to allocations from now on, returns the previous one.
+pub fn set_tag(tag: &'static str) -> &'static str {
+    core::mem::replace(&mut TRACKER.lock().tag, tag)
+}
+
+/// Runs `f` with allocations tagged `tag`, e.g. `with_tag(alloc_tag!(), || ...)`.
+pub fn with_tag<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+    let previous = set_tag(tag);
+    let result = f();
+    set_tag(previous);
+    result
+}
+
+pub fn checkpoint() -> Checkpoint {
+    let mut tracker = TRACKER.lock();
+    tracker.checkpoint += 1;
+    Checkpoint(tracker.checkpoint)
+}
+
+/// Runs `f` for every recorded allocation made after `checkpoint` that is still live.
+///
+/// `f` must not allocate.
+pub fn outstanding_since(checkpoint: Checkpoint, mut f: impl FnMut(AllocationRecord)) {
+    let tracker = TRACKER.lock();
+    tracker
+        .records
+        .iter()
+        .flatten()
+        .filter(|record| record.checkpoint >= checkpoint.0)
+        .for_each(|&record| f(record));
+}
+
+/// Prints every allocation made after `checkpoint` that is still live.
+pub fn dump_since(checkpoint: Checkpoint) {
+    let mut count = 0;
+    outstanding_since(checkpoint, |record| {
+        println!(
+            "leak? {:#x} {} bytes from {}",
+            record.addr, record.size, record.tag
+        );
+        count += 1;
+    });
+    let dropped = TRACKER.lock().dropped;
+    println!(
+        "{} outstanding allocations, {} not recorded",
+        count, dropped
+    );
+}
+
+pub(super) fn record_alloc(addr: usize, size: usize) {
+    let mut tracker = TRACKER.lock();
+    if !tracker.enabled {
+        return;
+    }
+    let record = AllocationRecord {
+        addr,
+        size,
+        tag: tracker.tag,
+        checkpoint: tracker.checkpoint,
+    };
+    match tracker.records.iter_mut().find(|r| r.is_none()) {
+        Some(slot) => *slot = Some(record),
+        None => tracker.dropped += 1,
+    }
+}
+
+pub(super) fn record_dealloc(addr: usize) {
+    let mut tracker = TRACKER.lock();
+    if !tracker.enabled {
+        return;
+    }
+    if let Some(slot) = tracker
+        .records
+        .iter_mut()
+        .find(|r| matches!(r, Some(record) if record.addr == addr))
+    {
+        *slot = None;
+    }
+}
+
+pub(super) fn record_resize(addr: usize, new_size: usize) {
+    let mut tracker = TRACKER.lock();
+    if !tracker.enabled {
+        return;
+    }
+    if let Some(Some(record)) = tracker
+        .records
+        .iter_mut()
+        .find(|r| matches!(r, Some(record) if record.addr == addr))
+    {
+        record.size = new_size;
+    }
+}
@@ -1,133 +1,19 @@
-use super::{align_up, Locked};
+use super::Locked;
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::{mem, ptr};
 
-struct ListNode {
-    size: usize,
-    next: Option<&'static mut ListNode>,
-}
-
-impl ListNode {
-    const fn new(size: usize) -> Self {
-        ListNode { size, next: None }
-    }
-
-    fn start_addr(&self) -> usize {
-        self as *const Self as usize
-    }
-
-    fn end_addr(&self) -> usize {
-        self.start_addr() + self.size
-    }
-}
-
-pub struct LinkedListAllocator {
-    head: ListNode,
-}
-
-impl LinkedListAllocator {
-    // construct empty linked list allocator
-    pub const fn new() -> Self {
-        Self {
-            head: ListNode::new(0),
-        }
-    }
-
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.add_free_region(heap_start, heap_size);
-    }
-
-    // add given region to front of list
-    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
-        // ensure region can hold list node
-        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
-        assert!(size >= mem::size_of::<ListNode>());
-
-        // create new list node and append it to start of list
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
-    }
-
-    // looks for appropriate region and removes it from free list
-    // returns list node and start addr of allocation
-    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
-        // reference to current list node, updated for each iteration
-        let mut current = &mut self.head;
-
-        // find large enough mem region in linked list
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                // region suitable for allocation -> remove node from list
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                // region not suitable -> continue wiht next region
-                current = current.next.as_mut().unwrap();
-            }
-        }
-
-        // no suitable region found
-        None
-    }
-
-    // try to use given region for allocation
-    // on success: returns start addr of allocation
-    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
-        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
-
-        if alloc_end > region.end_addr() {
-            // region too small
-            return Err(());
-        }
-
-        let excess_size = region.end_addr() - alloc_end;
-        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
-            // rest of region too small to hold ListNode
-            // required because allocation splits region into used and free parts
-            return Err(());
-        }
-
-        // region good for allocation
-        Ok(alloc_start)
-    }
-
-    // adjust layout so region can store ListNode as well
-    fn size_align(layout: Layout) -> (usize, usize) {
-        let layout = layout
-            .align_to(mem::align_of::<ListNode>())
-            .expect("adjusting alignment failed")
-            .pad_to_align();
-        let size = layout.size().max(mem::size_of::<ListNode>());
-        (size, layout.align())
-    }
-}
+pub use heap_allocators::linked_list::*;
 
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
-            }
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
-        }
+        self.alloc_with(layout, LinkedListAllocator::allocate)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.record_dealloc(ptr, layout);
+        self.lock().deallocate(ptr, layout);
+    }
 
-        self.lock().add_free_region(ptr as usize, size)
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc_with(ptr, layout, new_size, LinkedListAllocator::resize_in_place)
     }
 }
@@ -0,0 +1,103 @@
+use alloc::alloc::{alloc, Layout};
+use alloc::boxed::Box;
+use core::fmt;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+const MAX_HOOKS: usize = 8;
+// rounds of running the hooks and retrying before an allocation fails
+const RETRIES: usize = 3;
+
+/// Called when the heap is exhausted, to free memory that can be recreated later,
+/// like caches. Returns the number of bytes it freed.
+///
+/// Runs without any allocator lock held, so it may free and even allocate.
+pub type OomHook = fn(layout: Layout) -> usize;
+
+static HOOKS: Mutex<[Option<OomHook>; MAX_HOOKS]> = Mutex::new([None; MAX_HOOKS]);
+// set while the hooks run, an allocation failing inside a hook must not
+// start them again
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// An allocation of `layout` failed, even after the OOM hooks ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    pub layout: Layout,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "out of memory allocating {:?}", self.layout)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyHooks;
+
+/// Registers a hook to run before an allocation fails.
+pub fn register_hook(hook: OomHook) -> Result<(), TooManyHooks> {
+    let mut hooks = HOOKS.lock();
+    let slot = hooks.iter_mut().find(|h| h.is_none()).ok_or(TooManyHooks)?;
+    *slot = Some(hook);
+    Ok(())
+}
+
+pub fn unregister_hook(hook: OomHook) {
+    let mut hooks = HOOKS.lock();
+    for slot in hooks.iter_mut() {
+        if matches!(slot, Some(h) if *h as usize == hook as usize) {
+            *slot = None;
+        }
+    }
+}
+
+/// Allocates memory for `layout`, returning an error instead of panicking if
+/// the heap is exhausted.
+///
+/// Like every heap allocation, the OOM hooks get a chance to free memory first.
+/// The memory must be freed with `alloc::alloc::dealloc`.
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    if layout.size() == 0 {
+        // nothing to allocate, any aligned address will do
+        return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+    }
+    NonNull::new(unsafe { alloc(layout) }).ok_or(AllocError { layout })
+}
+
+/// Moves `value` to the heap, returning an error instead of panicking if the
+/// heap is exhausted.
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let ptr = try_alloc(Layout::new::<T>())?.cast::<T>();
+    unsafe {
+        ptr::write(ptr.as_ptr(), value);
+        Ok(Box::from_raw(ptr.as_ptr()))
+    }
+}
+
+// called by Locked with the allocator unlocked after an allocation failed
+// retries `allocate` as long as the hooks free some memory
+pub(super) fn retry(layout: Layout, mut allocate: impl FnMut() -> *mut u8) -> *mut u8 {
+    for _ in 0..RETRIES {
+        if run_hooks(layout) == 0 {
+            break;
+        }
+        let ptr = allocate();
+        if !ptr.is_null() {
+            return ptr;
+        }
+    }
+    ptr::null_mut()
+}
+
+// returns the number of bytes freed by all hooks
+fn run_hooks(layout: Layout) -> usize {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // copy, so hooks can (un)register hooks themselves
+    let hooks = *HOOKS.lock();
+    let freed = hooks.iter().flatten().map(|hook| hook(layout)).sum();
+    RUNNING.store(false, Ordering::Release);
+    freed
+}
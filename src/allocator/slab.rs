@@ -0,0 +1,266 @@
+use super::align_up;
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::mem;
+use core::ptr::{self, NonNull};
+
+// slabs are at least one page and hold at least this many objects
+const MIN_SLAB_SIZE: usize = 4096;
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+// link to the next free object, stored inside free objects
+type FreeLink = Option<NonNull<u8>>;
+
+// start of every slab, slabs are aligned to their size so the header of an
+// object's slab is found by masking its address
+struct SlabHeader {
+    next: Option<NonNull<SlabHeader>>,
+    prev: Option<NonNull<SlabHeader>>,
+    free_list: FreeLink,
+    in_use: usize,
+}
+
+/// Usage numbers of a `SlabCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    /// Slabs currently allocated from the heap.
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    /// Objects in allocated slabs that are ready to be handed out.
+    pub objects_free: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+/// A cache of equally sized objects, carved out of slabs taken from the global
+/// allocator. A slab goes back to the heap as soon as all its objects are freed.
+///
+/// If a constructor is given, it runs once for every object when its slab is
+/// created, not on every `alloc`. Objects must be freed in their constructed
+/// state, so the next `alloc` hands out an initialized object again.
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    align: usize,
+    constructor: Option<fn(*mut u8)>,
+    // offset of the free list link inside an object, behind the object's data
+    // if there is a constructor, so freeing doesn't clobber constructed state
+    link_offset: usize,
+    // distance between two objects in a slab
+    stride: usize,
+    // slabs with at least one free object, full slabs are not linked anywhere
+    partial: Option<NonNull<SlabHeader>>,
+    slabs: usize,
+    objects_in_use: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+// the raw pointers only point into slabs owned by the cache
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    /// Creates an empty cache for objects of `object_size` bytes aligned to `align`.
+    ///
+    /// `align` must be a power of two.
+    pub const fn new(
+        name: &'static str,
+        object_size: usize,
+        align: usize,
+        constructor: Option<fn(*mut u8)>,
+    ) -> Self {
+        // links are stored in the objects, so they need pointer alignment
+        let align = if align > mem::align_of::<FreeLink>() {
+            align
+        } else {
+            mem::align_of::<FreeLink>()
+        };
+        let link_offset = match constructor {
+            Some(_) => align_up(object_size, mem::align_of::<FreeLink>()),
+            None => 0,
+        };
+        let size = if object_size > link_offset + mem::size_of::<FreeLink>() {
+            object_size
+        } else {
+            link_offset + mem::size_of::<FreeLink>()
+        };
+
+        SlabCache {
+            name,
+            object_size,
+            align,
+            constructor,
+            link_offset,
+            stride: align_up(size, align),
+            partial: None,
+            slabs: 0,
+            objects_in_use: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            name: self.name,
+            object_size: self.object_size,
+            slabs: self.slabs,
+            objects_in_use: self.objects_in_use,
+            objects_free: self.slabs * self.objects_per_slab() - self.objects_in_use,
+            allocations: self.allocations,
+            frees: self.frees,
+        }
+    }
+
+    /// Hands out an object, taking a new slab from the heap if every slab is full.
+    ///
+    /// Returns None if the heap is exhausted.
+    pub fn alloc(&mut self) -> Option<NonNull<u8>> {
+        let slab = match self.partial {
+            Some(slab) => slab,
+            None => self.grow()?,
+        };
+
+        let object = unsafe {
+            let header = &mut *slab.as_ptr();
+            // slabs on the partial list always have a free object
+            let object = header.free_list.unwrap();
+            header.free_list = self.link(object).read();
+            header.in_use += 1;
+            if header.free_list.is_none() {
+                self.unlink(slab);
+            }
+            object
+        };
+
+        self.objects_in_use += 1;
+        self.allocations += 1;
+        Some(object)
+    }
+
+    /// Returns an object to the cache.
+    ///
+    /// Unsafe because `object` must have been handed out by `alloc` of this cache
+    /// and must not be used afterwards.
+    pub unsafe fn free(&mut self, object: NonNull<u8>) {
+        let slab_addr = object.as_ptr() as usize & !(self.slab_size() - 1);
+        let slab = NonNull::new_unchecked(slab_addr as *mut SlabHeader);
+        let header = &mut *slab.as_ptr();
+
+        let was_full = header.free_list.is_none();
+        self.link(object).write(header.free_list);
+        header.free_list = Some(object);
+        header.in_use -= 1;
+        self.objects_in_use -= 1;
+        self.frees += 1;
+
+        if header.in_use == 0 {
+            // last object gone, give the slab back to the heap
+            if !was_full {
+                self.unlink(slab);
+            }
+            dealloc(slab.as_ptr() as *mut u8, self.slab_layout());
+            self.slabs -= 1;
+        } else if was_full {
+            self.push(slab);
+        }
+    }
+
+    // the smallest power of two from MIN_SLAB_SIZE on that fits enough objects
+    fn slab_size(&self) -> usize {
+        let mut size = MIN_SLAB_SIZE;
+        while (size - self.first_object_offset()) / self.stride < MIN_OBJECTS_PER_SLAB {
+            size *= 2;
+        }
+        size
+    }
+
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size(), self.slab_size()).unwrap()
+    }
+
+    fn first_object_offset(&self) -> usize {
+        align_up(mem::size_of::<SlabHeader>(), self.align)
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        (self.slab_size() - self.first_object_offset()) / self.stride
+    }
+
+    fn link(&self, object: NonNull<u8>) -> *mut FreeLink {
+        unsafe { object.as_ptr().add(self.link_offset) as *mut FreeLink }
+    }
+
+    // take a new slab from the heap and put it on the partial list
+    fn grow(&mut self) -> Option<NonNull<SlabHeader>> {
+        let memory = NonNull::new(unsafe { alloc(self.slab_layout()) })?;
+        let slab = memory.cast::<SlabHeader>();
+
+        // build the free list back to front, so objects are handed out in address order
+        let mut free_list = None;
+        for i in (0..self.objects_per_slab()).rev() {
+            unsafe {
+                let object = memory
+                    .as_ptr()
+                    .add(self.first_object_offset() + i * self.stride);
+                let object = NonNull::new_unchecked(object);
+                if let Some(constructor) = self.constructor {
+                    constructor(object.as_ptr());
+                }
+                self.link(object).write(free_list);
+                free_list = Some(object);
+            }
+        }
+
+        unsafe {
+            ptr::write(
+                slab.as_ptr(),
+                SlabHeader {
+                    next: None,
+                    prev: None,
+                    free_list,
+                    in_use: 0,
+                },
+            );
+        }
+        self.push(slab);
+        self.slabs += 1;
+        Some(slab)
+    }
+
+    fn push(&mut self, slab: NonNull<SlabHeader>) {
+        unsafe {
+            let header = &mut *slab.as_ptr();
+            header.prev = None;
+            header.next = self.partial;
+            if let Some(next) = self.partial {
+                (*next.as_ptr()).prev = Some(slab);
+            }
+        }
+        self.partial = Some(slab);
+    }
+
+    fn unlink(&mut self, slab: NonNull<SlabHeader>) {
+        unsafe {
+            let header = &mut *slab.as_ptr();
+            match header.prev {
+                Some(prev) => (*prev.as_ptr()).next = header.next,
+                None => self.partial = header.next,
+            }
+            if let Some(next) = header.next {
+                (*next.as_ptr()).prev = header.prev;
+            }
+            header.next = None;
+            header.prev = None;
+        }
+    }
+}
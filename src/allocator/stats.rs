@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Snapshot of the allocations that went through a `Locked` allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Successful allocations since boot.
+    pub allocations: u64,
+    pub deallocations: u64,
+    /// Allocations that returned null.
+    pub failed_allocations: u64,
+    /// Requested bytes of all live allocations, without allocator overhead.
+    pub bytes_in_use: usize,
+    /// Highest `bytes_in_use` so far.
+    pub peak_bytes_in_use: usize,
+}
+
+impl HeapStats {
+    pub fn live_allocations(&self) -> u64 {
+        self.allocations - self.deallocations
+    }
+}
+
+// atomics, so updating them needs no lock besides the allocator's own
+pub(super) struct Counters {
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+    failed_allocations: AtomicU64,
+    bytes_in_use: AtomicUsize,
+    peak_bytes_in_use: AtomicUsize,
+}
+
+impl Counters {
+    pub(super) const fn new() -> Self {
+        Counters {
+            allocations: AtomicU64::new(0),
+            deallocations: AtomicU64::new(0),
+            failed_allocations: AtomicU64::new(0),
+            bytes_in_use: AtomicUsize::new(0),
+            peak_bytes_in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn record_alloc(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        let in_use = self.bytes_in_use.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes_in_use.fetch_max(in_use, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_failure(&self) {
+        self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_dealloc(&self, size: usize) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in_use.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    // an allocation resized in place, counts neither as allocation nor as deallocation
+    pub(super) fn record_resize(&self, old_size: usize, new_size: usize) {
+        if new_size > old_size {
+            let grown = new_size - old_size;
+            let in_use = self.bytes_in_use.fetch_add(grown, Ordering::Relaxed) + grown;
+            self.peak_bytes_in_use.fetch_max(in_use, Ordering::Relaxed);
+        } else {
+            self.bytes_in_use
+                .fetch_sub(old_size - new_size, Ordering::Relaxed);
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> HeapStats {
+        HeapStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+            bytes_in_use: self.bytes_in_use.load(Ordering::Relaxed),
+            peak_bytes_in_use: self.peak_bytes_in_use.load(Ordering::Relaxed),
+        }
+    }
+}
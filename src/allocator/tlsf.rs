@@ -0,0 +1,19 @@
+use super::Locked;
+use alloc::alloc::{GlobalAlloc, Layout};
+
+pub use heap_allocators::tlsf::*;
+
+unsafe impl GlobalAlloc for Locked<TlsfAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, TlsfAllocator::allocate)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.record_dealloc(ptr, layout);
+        self.lock().deallocate(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc_with(ptr, layout, new_size, TlsfAllocator::resize_in_place)
+    }
+}
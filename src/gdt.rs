@@ -1,4 +1,6 @@
+use crate::memory::stack::{self, StackError};
 use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
 use x86_64::structures::tss::TaskStateSegment;
@@ -7,26 +9,32 @@ use x86_64::VirtAddr;
 // Define double fault stack as 0th IST entry
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+// pages of each guarded IST stack
+const IST_STACK_PAGES: u64 = 5;
+
+// mutable so the IST stacks can be replaced by guarded ones once memory is set up
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+// bootstrap double fault stack, used until init_guarded_stacks runs
+// has no guard page, overflowing it overwrites neighbouring statics
+fn bootstrap_stack() -> VirtAddr {
+    const STACK_SIZE: usize = 4096 * 5;
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+    let stack_end = stack_start + STACK_SIZE;
+    stack_end
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss = unsafe {
+            TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = bootstrap_stack();
+            &TSS
+        };
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
         (
             gdt,
             Selectors {
@@ -52,3 +60,16 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Replaces the IST stacks with stacks from the kernel stack allocator, which
+/// have a guard page below them.
+///
+/// Needs `memory::init_kernel_memory` to be called first.
+pub fn init_guarded_stacks() -> Result<(), StackError> {
+    let stack = stack::allocate(IST_STACK_PAGES)?;
+    // the TSS is only read by the CPU when an exception switches stacks
+    interrupts::without_interrupts(|| unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack.leak();
+    });
+    Ok(())
+}
@@ -1,8 +1,17 @@
-use crate::{gdt, hlt_loop, print, println};
+use crate::{gdt, memory, print, println};
+use apic::{ApicError, LocalApic};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use ioapic::IoApic;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::PhysAddr;
+
+pub mod apic;
+mod exceptions;
+pub mod ioapic;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -19,6 +28,11 @@ impl InterruptIndex {
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
+
+    // the ISA IRQ line, same as the 8259 input
+    fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
 }
 
 // Set programmable interrupt controller interrupt vector range to 32 - 47
@@ -28,6 +42,15 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+// set once interrupts go through the APIC instead of the PICs
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+pub static IO_APIC: spin::Mutex<Option<IoApic>> = spin::Mutex::new(None);
+
+// about the rate the PIT runs at by default
+const TIMER_INTERVAL_MS: u32 = 55;
+
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -38,8 +61,10 @@ lazy_static! {
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
         idt.page_fault.set_handler_fn(page_fault_handler);
+        exceptions::set_handlers(&mut idt);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -49,6 +74,49 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Moves interrupt delivery from the 8259 PICs to the local APIC and IOAPIC.
+///
+/// The timer switches to the APIC timer and the keyboard IRQ is routed through
+/// the IOAPIC, then both PICs are masked. On error the PICs stay in charge.
+/// Requires `memory::init_kernel_memory` to be called before.
+pub fn init_apic() -> Result<(), ApicError> {
+    if LOCAL_APIC.is_initialized() {
+        return Ok(());
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let local_apic = unsafe { LocalApic::init()? };
+        let mut io_apic = unsafe { IoApic::init(PhysAddr::new(ioapic::DEFAULT_BASE))? };
+
+        local_apic.start_timer(InterruptIndex::Timer.as_u8(), TIMER_INTERVAL_MS);
+        let keyboard = InterruptIndex::Keyboard;
+        io_apic.route(keyboard.irq(), keyboard.as_u8(), local_apic.id());
+
+        unsafe { PICS.lock().disable() };
+        *IO_APIC.lock() = Some(io_apic);
+        LOCAL_APIC.init_once(|| local_apic);
+        Ok(())
+    })
+}
+
+/// The local APIC, if `init_apic` switched to it.
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+/// Number of timer interrupts since boot.
+pub fn timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+// acknowledge the interrupt at whichever controller delivered it
+fn end_of_interrupt(index: InterruptIndex) {
+    match LOCAL_APIC.get() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) },
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
@@ -58,17 +126,25 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    // a page fault in a guard page can't be delivered on the overflowed stack
+    let accessed_address = Cr2::read();
+    if memory::stack::is_guard_page(accessed_address) {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT (kernel stack overflow)\nAccessed Address: {:?}\n{:#?}",
+            accessed_address, stack_frame
+        );
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
     print!(".");
+    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
 
     // Send end of interrupt signal
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Timer);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -78,12 +154,13 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
+// raised by the local APIC when an interrupt went away before it was delivered,
+// there is nothing to acknowledge
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
@@ -91,11 +168,20 @@ extern "x86-interrupt" fn page_fault_handler(
     // Cr2 register has virtual memory address that caused fault
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+    let accessed_address = Cr2::read();
+    // not-present page in a demand paged region -> map it and retry the access
+    if memory::lazy::handle_page_fault(accessed_address, error_code) {
+        return;
+    }
+    // write to a shared copy-on-write page -> give it a private copy and retry
+    if memory::cow::handle_page_fault(accessed_address, error_code) {
+        return;
+    }
+
+    panic!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        accessed_address, error_code, stack_frame
+    );
 }
 
 #[test_case]
@@ -0,0 +1,172 @@
+use crate::memory::mmio::{ioremap, CacheMode, MmioError, MmioRegion};
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// register offsets
+const ID: u64 = 0x20;
+const TASK_PRIORITY: u64 = 0x80;
+const EOI: u64 = 0xb0;
+const SPURIOUS: u64 = 0xf0;
+const ICR_LOW: u64 = 0x300;
+const ICR_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const LVT_LINT0: u64 = 0x350;
+const TIMER_INITIAL_COUNT: u64 = 0x380;
+const TIMER_CURRENT_COUNT: u64 = 0x390;
+const TIMER_DIVIDE: u64 = 0x3e0;
+const REGISTERS_SIZE: u64 = 0x400;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_TO_SELF: u32 = 0b01 << 18;
+
+/// Vector of the spurious interrupts the local APIC raises now and then, they
+/// must not be acknowledged.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+// PIT, only used to measure the speed of the APIC timer
+const PIT_FREQUENCY: u32 = 1_193_182;
+const CALIBRATION_MS: u32 = 10;
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// CPUID says there is no local APIC.
+    NotSupported,
+    Map(MmioError),
+}
+
+/// Returns true if the CPU has a local APIC (CPUID `apic`).
+pub fn is_supported() -> bool {
+    let features = unsafe { core::arch::x86_64::__cpuid(1) };
+    features.edx & (1 << 9) != 0
+}
+
+/// The local APIC of the current CPU, in xAPIC mode.
+#[derive(Debug)]
+pub struct LocalApic {
+    registers: MmioRegion,
+    // timer ticks per millisecond, with the divider set to 16
+    ticks_per_ms: u32,
+}
+
+impl LocalApic {
+    /// Maps the registers of the local APIC and enables it.
+    ///
+    /// Requires `memory::init_kernel_memory` to be called before. Unsafe because
+    /// only one `LocalApic` may exist per CPU.
+    pub unsafe fn init() -> Result<LocalApic, ApicError> {
+        if !is_supported() {
+            return Err(ApicError::NotSupported);
+        }
+
+        let mut base_msr = Msr::new(IA32_APIC_BASE);
+        let base = base_msr.read();
+        base_msr.write(base | APIC_BASE_ENABLE);
+        let phys_addr = PhysAddr::new(base & APIC_BASE_ADDR_MASK);
+        let registers =
+            ioremap(phys_addr, REGISTERS_SIZE, CacheMode::Uncached).map_err(ApicError::Map)?;
+
+        let mut apic = LocalApic {
+            registers,
+            ticks_per_ms: 0,
+        };
+        apic.write(TASK_PRIORITY, 0);
+        // the 8259 is wired to LINT0, cut it off so only the IOAPIC delivers IRQs
+        apic.write(LVT_LINT0, LVT_MASKED);
+        apic.write(SPURIOUS, SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR));
+        apic.ticks_per_ms = apic.calibrate_timer();
+        Ok(apic)
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(ID) >> 24) as u8
+    }
+
+    /// Signals the end of the interrupt currently being handled.
+    pub fn end_of_interrupt(&self) {
+        self.write(EOI, 0);
+    }
+
+    /// Raises `vector` every `interval_ms` milliseconds.
+    pub fn start_timer(&self, vector: u8, interval_ms: u32) {
+        self.write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, TIMER_PERIODIC | u32::from(vector));
+        self.write(
+            TIMER_INITIAL_COUNT,
+            self.ticks_per_ms.saturating_mul(interval_ms),
+        );
+    }
+
+    pub fn stop_timer(&self) {
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL_COUNT, 0);
+    }
+
+    /// Sends an interrupt with `vector` to the CPU with the local APIC `apic_id`.
+    pub fn send_ipi(&self, apic_id: u8, vector: u8) {
+        self.write(ICR_HIGH, u32::from(apic_id) << 24);
+        // writing the low half sends the IPI
+        self.write(ICR_LOW, ICR_ASSERT | u32::from(vector));
+        self.wait_for_delivery();
+    }
+
+    /// Sends an interrupt with `vector` to the current CPU.
+    pub fn send_self_ipi(&self, vector: u8) {
+        self.write(ICR_LOW, ICR_TO_SELF | ICR_ASSERT | u32::from(vector));
+        self.wait_for_delivery();
+    }
+
+    fn wait_for_delivery(&self) {
+        while self.read(ICR_LOW) & ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    // counts down the timer for CALIBRATION_MS, timed by PIT channel 2
+    // returns the timer ticks per millisecond
+    fn calibrate_timer(&self) -> u32 {
+        let mut control = Port::<u8>::new(0x61);
+        let mut command = Port::<u8>::new(0x43);
+        let mut channel_2 = Port::<u8>::new(0x42);
+        let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+        self.write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, LVT_MASKED);
+        unsafe {
+            // gate of channel 2 low and the speaker off while programming
+            let gate = control.read() & !0b11;
+            control.write(gate);
+            // channel 2, lobyte/hibyte, mode 0: output goes high once the count is reached
+            command.write(0b1011_0000);
+            channel_2.write(count as u8);
+            channel_2.write((count >> 8) as u8);
+
+            control.write(gate | 1);
+            self.write(TIMER_INITIAL_COUNT, u32::MAX);
+            while control.read() & (1 << 5) == 0 {
+                core::hint::spin_loop();
+            }
+            let elapsed = u32::MAX - self.read(TIMER_CURRENT_COUNT);
+            self.write(TIMER_INITIAL_COUNT, 0);
+            control.write(gate);
+            elapsed / CALIBRATION_MS
+        }
+    }
+
+    fn read(&self, offset: u64) -> u32 {
+        self.registers.read(offset)
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        self.registers.write(offset, value)
+    }
+}
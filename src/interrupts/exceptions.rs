@@ -0,0 +1,390 @@
+use crate::vga_buffer::WRITER;
+use core::arch::global_asm;
+use core::fmt;
+use x86_64::registers::control::{Cr0, Cr3, Cr4};
+use x86_64::structures::idt::{
+    Entry, InterruptDescriptorTable, InterruptStackFrameValue, SelectorErrorCode,
+};
+use x86_64::VirtAddr;
+
+// Entry stubs for the CPU exceptions interrupts.rs doesn't handle itself
+// x86-interrupt handlers don't get to see the general purpose registers of the
+// interrupted code, so every stub pushes a vector number, an error code (0 if
+// the CPU doesn't push one) and the registers on top of the interrupt stack
+// frame, then calls exception_handler with a pointer to all of it
+// the CPU aligns the stack to 16 bytes before pushing its frame, and the stub
+// pushes 17 more values on top of the 5 of the frame, so it's still aligned
+// at the call
+global_asm!(
+    r#"
+.macro exception_entry vector, error_code
+.global exception_entry_\vector
+exception_entry_\vector:
+.if \error_code == 0
+    push 0
+.endif
+    push \vector
+    jmp exception_entry_common
+.endm
+
+exception_entry_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    cld
+    call exception_handler
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    // vector and error code
+    add rsp, 16
+    iretq
+
+exception_entry 0, 0
+exception_entry 1, 0
+exception_entry 2, 0
+exception_entry 4, 0
+exception_entry 5, 0
+exception_entry 6, 0
+exception_entry 7, 0
+exception_entry 10, 1
+exception_entry 11, 1
+exception_entry 12, 1
+exception_entry 13, 1
+exception_entry 16, 0
+exception_entry 17, 1
+exception_entry 18, 0
+exception_entry 19, 0
+exception_entry 20, 0
+exception_entry 21, 1
+exception_entry 28, 0
+exception_entry 29, 1
+exception_entry 30, 1
+"#
+);
+
+extern "C" {
+    fn exception_entry_0();
+    fn exception_entry_1();
+    fn exception_entry_2();
+    fn exception_entry_4();
+    fn exception_entry_5();
+    fn exception_entry_6();
+    fn exception_entry_7();
+    fn exception_entry_10();
+    fn exception_entry_11();
+    fn exception_entry_12();
+    fn exception_entry_13();
+    fn exception_entry_16();
+    fn exception_entry_17();
+    fn exception_entry_18();
+    fn exception_entry_19();
+    fn exception_entry_20();
+    fn exception_entry_21();
+    fn exception_entry_28();
+    fn exception_entry_29();
+    fn exception_entry_30();
+}
+
+// faults panic with a decoded error code, the faulting RIP and the registers,
+// instead of escalating to a double fault that hides the cause
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        set_entry(&mut idt.divide_error, exception_entry_0);
+        set_entry(&mut idt.debug, exception_entry_1);
+        set_entry(&mut idt.non_maskable_interrupt, exception_entry_2);
+        set_entry(&mut idt.overflow, exception_entry_4);
+        set_entry(&mut idt.bound_range_exceeded, exception_entry_5);
+        set_entry(&mut idt.invalid_opcode, exception_entry_6);
+        set_entry(&mut idt.device_not_available, exception_entry_7);
+        set_entry(&mut idt.invalid_tss, exception_entry_10);
+        set_entry(&mut idt.segment_not_present, exception_entry_11);
+        set_entry(&mut idt.stack_segment_fault, exception_entry_12);
+        set_entry(&mut idt.general_protection_fault, exception_entry_13);
+        set_entry(&mut idt.x87_floating_point, exception_entry_16);
+        set_entry(&mut idt.alignment_check, exception_entry_17);
+        set_entry(&mut idt.machine_check, exception_entry_18);
+        set_entry(&mut idt.simd_floating_point, exception_entry_19);
+        set_entry(&mut idt.virtualization, exception_entry_20);
+        set_entry(&mut idt.cp_protection_exception, exception_entry_21);
+        set_entry(&mut idt.hv_injection_exception, exception_entry_28);
+        set_entry(&mut idt.vmm_communication_exception, exception_entry_29);
+        set_entry(&mut idt.security_exception, exception_entry_30);
+    }
+}
+
+// unsafe because `stub` must be one of the entry stubs above
+unsafe fn set_entry<F>(entry: &mut Entry<F>, stub: unsafe extern "C" fn()) {
+    entry.set_handler_addr(VirtAddr::new(stub as usize as u64));
+}
+
+// what the entry stubs leave on the stack, lowest address first
+#[repr(C)]
+struct ExceptionState {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    vector: u64,
+    error_code: u64,
+    frame: InterruptStackFrameValue,
+}
+
+// the state at the time of the exception: the frame the CPU pushed, the
+// general purpose registers the entry stub saved and the control registers
+struct Registers<'a>(&'a ExceptionState);
+
+impl fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.0;
+        let frame = &state.frame;
+        let mode = if frame.code_segment & 0b11 == 3 {
+            "user"
+        } else {
+            "kernel"
+        };
+        writeln!(
+            f,
+            "RIP: {:#018x}  CS: {:#06x} ({} mode)  RFLAGS: {:#x}",
+            frame.instruction_pointer.as_u64(),
+            frame.code_segment,
+            mode,
+            frame.cpu_flags
+        )?;
+        writeln!(
+            f,
+            "RSP: {:#018x}  SS: {:#06x}",
+            frame.stack_pointer.as_u64(),
+            frame.stack_segment
+        )?;
+        writeln!(
+            f,
+            "RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}",
+            state.rax, state.rbx, state.rcx
+        )?;
+        writeln!(
+            f,
+            "RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}",
+            state.rdx, state.rsi, state.rdi
+        )?;
+        writeln!(
+            f,
+            "RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}",
+            state.rbp, state.r8, state.r9
+        )?;
+        writeln!(
+            f,
+            "R10: {:#018x}  R11: {:#018x}  R12: {:#018x}",
+            state.r10, state.r11, state.r12
+        )?;
+        writeln!(
+            f,
+            "R13: {:#018x}  R14: {:#018x}  R15: {:#018x}",
+            state.r13, state.r14, state.r15
+        )?;
+        // no CR2, it only means something for page faults
+        write!(
+            f,
+            "CR0: {:#x}  CR3: {:#x}  CR4: {:#x}",
+            Cr0::read_raw(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+// error code of #TS, #NP, #SS and #GP, which names the selector at fault if
+// there is one
+struct Selector(u64);
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match SelectorErrorCode::new(self.0) {
+            Some(selector) if !selector.is_null() => write!(
+                f,
+                "{:?} index {}{} (error code {:#x})",
+                selector.descriptor_table(),
+                selector.index(),
+                if selector.external() {
+                    ", external"
+                } else {
+                    ""
+                },
+                self.0
+            ),
+            _ => write!(f, "none (error code {:#x})", self.0),
+        }
+    }
+}
+
+// faults can't be returned from, the faulting instruction would just run again
+fn fault(name: &str, details: fmt::Arguments, state: &ExceptionState) -> ! {
+    panic!("EXCEPTION: {}\n{}{}", name, details, Registers(state));
+}
+
+// called by the entry stubs, returning resumes the interrupted code
+#[no_mangle]
+extern "C" fn exception_handler(state: &ExceptionState) {
+    let error_code = state.error_code;
+    match state.vector {
+        0 => fault(
+            "DIVIDE ERROR",
+            format_args!("division by zero or quotient too large\n"),
+            state,
+        ),
+        1 => debug(state),
+        // reported, but nothing in the kernel uses NMIs yet
+        2 => report(format_args!(
+            "EXCEPTION: NON-MASKABLE INTERRUPT\n{}",
+            Registers(state)
+        )),
+        4 => fault("OVERFLOW", format_args!("INTO with OF set\n"), state),
+        5 => fault("BOUND RANGE EXCEEDED", format_args!(""), state),
+        6 => fault("INVALID OPCODE", format_args!(""), state),
+        7 => fault(
+            "DEVICE NOT AVAILABLE",
+            format_args!("FPU/SSE instruction with CR0.TS or CR0.EM set\n"),
+            state,
+        ),
+        10 => fault(
+            "INVALID TSS",
+            format_args!("Selector: {}\n", Selector(error_code)),
+            state,
+        ),
+        11 => fault(
+            "SEGMENT NOT PRESENT",
+            format_args!("Selector: {}\n", Selector(error_code)),
+            state,
+        ),
+        12 => fault(
+            "STACK SEGMENT FAULT",
+            format_args!("Selector: {}\n", Selector(error_code)),
+            state,
+        ),
+        13 => fault(
+            "GENERAL PROTECTION FAULT",
+            format_args!("Selector: {}\n", Selector(error_code)),
+            state,
+        ),
+        16 => x87_floating_point(state),
+        // error code is always 0
+        17 => fault("ALIGNMENT CHECK", format_args!(""), state),
+        18 => fault(
+            "MACHINE CHECK",
+            format_args!("the CPU detected a hardware error\n"),
+            state,
+        ),
+        19 => simd_floating_point(state),
+        20 => fault("VIRTUALIZATION", format_args!(""), state),
+        21 => control_protection(state),
+        28 => fault("HYPERVISOR INJECTION", format_args!(""), state),
+        29 => fault(
+            "VMM COMMUNICATION",
+            format_args!("Exit code: {:#x}\n", error_code),
+            state,
+        ),
+        30 => fault(
+            "SECURITY EXCEPTION",
+            format_args!("Error code: {:#x}\n", error_code),
+            state,
+        ),
+        vector => fault("UNEXPECTED", format_args!("Vector: {}\n", vector), state),
+    }
+}
+
+// single stepping and hardware breakpoints, there is no debugger to hand them to
+fn debug(state: &ExceptionState) {
+    let dr6: u64;
+    unsafe { core::arch::asm!("mov {}, dr6", out(reg) dr6, options(nomem, nostack)) };
+    report(format_args!(
+        "EXCEPTION: DEBUG\nDR6: {:#x}\n{}",
+        dr6,
+        Registers(state)
+    ));
+}
+
+// for the exceptions that return, which can arrive while the interrupted code
+// holds the writer lock (NMIs aren't masked by cli), so waiting for it would
+// deadlock. The report is dropped instead
+fn report(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    if let Some(mut writer) = WRITER.try_lock() {
+        writer.write_fmt(args).ok();
+        writer.write_str("\n").ok();
+    }
+}
+
+fn x87_floating_point(state: &ExceptionState) -> ! {
+    let status: u16;
+    unsafe { core::arch::asm!("fnstsw ax", out("ax") status, options(nomem, nostack)) };
+    fault(
+        "X87 FLOATING POINT",
+        format_args!("FPU status word: {:#06x}\n", status),
+        state,
+    );
+}
+
+fn simd_floating_point(state: &ExceptionState) -> ! {
+    let mut mxcsr: u32 = 0;
+    unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr, options(nostack)) };
+    // the six exception flags: invalid, denormal, divide by zero, overflow,
+    // underflow, precision
+    fault(
+        "SIMD FLOATING POINT",
+        format_args!("MXCSR: {:#x} (flags {:#08b})\n", mxcsr, mxcsr & 0x3f),
+        state,
+    );
+}
+
+fn control_protection(state: &ExceptionState) -> ! {
+    let cause = match state.error_code & 0x7fff {
+        1 => "near return",
+        2 => "far return or iret",
+        3 => "missing endbranch",
+        4 => "rstorssp",
+        5 => "setssbsy",
+        _ => "unknown",
+    };
+    fault(
+        "CONTROL PROTECTION",
+        format_args!("Cause: {} (error code {:#x})\n", cause, state.error_code),
+        state,
+    );
+}
@@ -0,0 +1,99 @@
+use super::apic::ApicError;
+use crate::memory::mmio::{ioremap, CacheMode, MmioRegion};
+use x86_64::PhysAddr;
+
+/// Where the IOAPIC lives on PCs and QEMU. The ACPI MADT would tell if it is
+/// somewhere else, but there is no ACPI support yet.
+pub const DEFAULT_BASE: u64 = 0xfec0_0000;
+
+// the registers are reached by writing their index to IOREGSEL and then
+// accessing IOWIN
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const REGISTERS_SIZE: u64 = 0x20;
+
+const VERSION: u32 = 0x01;
+const REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_MASKED: u32 = 1 << 16;
+
+/// Where an IRQ input is delivered, as read back from its redirection entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redirection {
+    pub vector: u8,
+    pub apic_id: u8,
+    pub masked: bool,
+}
+
+/// An IOAPIC, which routes device IRQs to local APICs.
+///
+/// ISA IRQs are delivered edge triggered and active high, like the 8259 did.
+/// The legacy timer is usually moved from IRQ 0 to input 2, which is why only
+/// the APIC timer is used.
+#[derive(Debug)]
+pub struct IoApic {
+    registers: MmioRegion,
+}
+
+impl IoApic {
+    /// Maps the IOAPIC at `phys_addr` and masks all of its inputs.
+    ///
+    /// Requires `memory::init_kernel_memory` to be called before. Unsafe because
+    /// `phys_addr` must be the address of an IOAPIC nobody else uses.
+    pub unsafe fn init(phys_addr: PhysAddr) -> Result<IoApic, ApicError> {
+        let registers =
+            ioremap(phys_addr, REGISTERS_SIZE, CacheMode::Uncached).map_err(ApicError::Map)?;
+        let mut ioapic = IoApic { registers };
+        for irq in 0..ioapic.inputs() {
+            ioapic.mask(irq);
+        }
+        Ok(ioapic)
+    }
+
+    /// Number of IRQ inputs, i.e. redirection table entries.
+    pub fn inputs(&self) -> u8 {
+        (self.read(VERSION) >> 16) as u8 + 1
+    }
+
+    /// Delivers `irq` as `vector` to the local APIC with id `apic_id` and unmasks it.
+    pub fn route(&mut self, irq: u8, vector: u8, apic_id: u8) {
+        assert!(irq < self.inputs(), "IOAPIC has no input {}", irq);
+        let entry = REDIRECTION_TABLE + 2 * u32::from(irq);
+        // fixed delivery, physical destination, active high, edge triggered
+        self.write(entry + 1, u32::from(apic_id) << 24);
+        self.write(entry, u32::from(vector));
+    }
+
+    pub fn redirection(&self, irq: u8) -> Redirection {
+        assert!(irq < self.inputs(), "IOAPIC has no input {}", irq);
+        let entry = REDIRECTION_TABLE + 2 * u32::from(irq);
+        let low = self.read(entry);
+        Redirection {
+            vector: low as u8,
+            apic_id: (self.read(entry + 1) >> 24) as u8,
+            masked: low & ENTRY_MASKED != 0,
+        }
+    }
+
+    pub fn mask(&mut self, irq: u8) {
+        let entry = REDIRECTION_TABLE + 2 * u32::from(irq);
+        let low = self.read(entry);
+        self.write(entry, low | ENTRY_MASKED);
+    }
+
+    pub fn unmask(&mut self, irq: u8) {
+        let entry = REDIRECTION_TABLE + 2 * u32::from(irq);
+        let low = self.read(entry);
+        self.write(entry, low & !ENTRY_MASKED);
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        self.registers.write(IOREGSEL, register);
+        self.registers.read(IOWIN)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write(IOREGSEL, register);
+        self.registers.write(IOWIN, value);
+    }
+}
@@ -6,6 +6,7 @@
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
 #![feature(const_mut_refs)]
+#![feature(allocator_api)]
 
 use core::panic::PanicInfo;
 
@@ -100,6 +101,8 @@ pub fn init() {
     x86_64::instructions::interrupts::enable();
 }
 
+// only reached once the OOM hooks couldn't free enough memory, code that can
+// handle running out of memory uses allocator::oom::try_alloc instead
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("allocation error: {:?}", layout)
@@ -23,7 +23,8 @@ entry_point!(kernel_main);
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use rust_os::{
         allocator,
-        memory::{self, BootInfoFrameAllocator},
+        memory::{self, buddy::BuddyFrameAllocator, region::RegionKind},
+        vga_buffer,
     };
     use x86_64::{structures::paging::Page, VirtAddr};
 
@@ -32,10 +33,14 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    // W^X for the bootloader's mappings, write protection and SMEP/SMAP
+    unsafe { memory::protect::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
 
     // map an unused page
-    let page = Page::containing_address(VirtAddr::new(0xdeadbeeff000));
+    let region = memory::region::allocate(4096, 4096, RegionKind::Other).expect("no free page");
+    let page = Page::containing_address(region.start);
     memory::create_example_mapping(page, &mut mapper, &mut frame_allocator);
 
     // write "New!" to screen using new mapping
@@ -43,6 +48,15 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     unsafe { page_ptr.offset(400).write_volatile(0x_f021_f077_f065_f04e) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    vga_buffer::remap().expect("failed to remap the VGA buffer");
+    rust_os::gdt::init_guarded_stacks().expect("guarded stack allocation failed");
+    // the PICs keep delivering interrupts if there is no APIC
+    if let Err(err) = rust_os::interrupts::init_apic() {
+        println!("APIC unavailable, staying on the 8259 PICs: {:?}", err);
+    }
+    memory::stats::init(&boot_info.memory_map);
+    memory::stats::print();
 
     // allocate number on heap
     let heap_value = Box::new(41);
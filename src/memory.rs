@@ -1,9 +1,37 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use buddy::BuddyFrameAllocator;
+use region::RegionKind;
+use spin::Mutex;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::{MapToError, MappedFrame, TranslateResult, UnmapError};
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
+pub mod address_space;
+pub mod buddy;
+pub mod cow;
+pub mod elf;
+pub mod lazy;
+pub mod mmio;
+pub mod protect;
+pub mod region;
+pub mod stack;
+pub mod stats;
+
+// mapper and frame allocator owned by the kernel once booting is done
+// used from contexts that can't get them passed in, like the page fault handler
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BuddyFrameAllocator,
+    // the kernel's own level 4 table, active while no other address space is
+    level_4_frame: PhysFrame,
+    frame_refs: cow::FrameRefCounts,
+}
+
 // Get a mutable reference to the active level 4 table
 // Unsafe because caller must guarantee complete physical memory is mapped
 // at the passed offset
@@ -20,12 +48,149 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr
 }
 
+// the bootloader maps all physical memory into a single level 4 entry
+const PHYSICAL_MAP_SIZE: u64 = 512 * 1024 * 1024 * 1024;
+
 // initialize new offset page table
+// the bootloader's mappings stay as they are until protect::init locks them down
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    // the kernel maps data NO_EXECUTE, which is a reserved bit until NXE is on
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    region::reserve(
+        physical_memory_offset,
+        PHYSICAL_MAP_SIZE,
+        RegionKind::PhysicalMap,
+    )
+    .expect("physical memory map overlaps another region");
+    region::reserve(
+        VirtAddr::new(address_space::USER_SPACE_START),
+        address_space::USER_SPACE_END - address_space::USER_SPACE_START,
+        RegionKind::User,
+    )
+    .expect("user space range overlaps another region");
+
+    let (kernel_start, kernel_end) = elf::kernel_image();
+    region::reserve(
+        VirtAddr::new(kernel_start),
+        kernel_end - kernel_start,
+        RegionKind::Kernel,
+    )
+    .expect("kernel image overlaps another region");
+
     let level_4_table = active_level_4_table(physical_memory_offset);
+    reserve_boot_mappings(level_4_table, physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+// The boot info only describes physical memory, so find the rest of what the
+// bootloader mapped (its stack, the boot info itself, the identity mapped pages
+// it switched to the kernel from) by walking the page tables, and reserve every
+// contiguous run not covered by the kernel image or the physical memory map.
+unsafe fn reserve_boot_mappings(level_4_table: &PageTable, physical_memory_offset: VirtAddr) {
+    let mut run: Option<(u64, u64)> = None;
+    walk_mappings(
+        level_4_table,
+        4,
+        0,
+        physical_memory_offset,
+        &mut |start, size| {
+            if region::find_overlapping(VirtAddr::new(start), size).is_some() {
+                return;
+            }
+            match run {
+                Some((run_start, run_end)) if run_end == start => {
+                    run = Some((run_start, start + size))
+                }
+                _ => {
+                    if let Some((run_start, run_end)) = run {
+                        reserve_boot_run(run_start, run_end);
+                    }
+                    run = Some((start, start + size));
+                }
+            }
+        },
+    );
+    if let Some((run_start, run_end)) = run {
+        reserve_boot_run(run_start, run_end);
+    }
+}
+
+fn reserve_boot_run(start: u64, end: u64) {
+    region::reserve(VirtAddr::new(start), end - start, RegionKind::Boot)
+        .expect("failed to reserve bootloader mapping");
+}
+
+// calls `f` with the start and size of every mapped page below `table`, which
+// maps the range starting at `start`, skipping entries inside a reserved region
+unsafe fn walk_mappings(
+    table: &PageTable,
+    level: u8,
+    start: u64,
+    physical_memory_offset: VirtAddr,
+    f: &mut dyn FnMut(u64, u64),
+) {
+    let entry_size = 1u64 << (12 + 9 * (level as u64 - 1));
+    for (i, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let addr = VirtAddr::new_truncate(start + i as u64 * entry_size);
+        if matches!(region::find(addr), Some(r) if r.end() >= addr + entry_size) {
+            // e.g. the physical memory map, no need to look at every page of it
+            continue;
+        }
+
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            f(addr.as_u64(), entry_size);
+        } else {
+            let table = &*(physical_memory_offset + entry.addr().as_u64()).as_ptr();
+            walk_mappings(table, level - 1, addr.as_u64(), physical_memory_offset, f);
+        }
+    }
+}
+
+/// Hands the kernel's mapper and frame allocator over to the memory subsystem.
+///
+/// Also gives every level 4 entry outside the user space range a level 3 table,
+/// so that mappings the kernel creates later show up in every address space.
+pub fn init_kernel_memory(
+    mut mapper: OffsetPageTable<'static>,
+    mut frame_allocator: BuddyFrameAllocator,
+) {
+    use x86_64::registers::control::Cr3;
+
+    address_space::preallocate_kernel_tables(&mut mapper, &mut frame_allocator);
+    let frame_refs = cow::FrameRefCounts::new(&mut frame_allocator, mapper.phys_offset());
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+        level_4_frame: Cr3::read().0,
+        frame_refs,
+    });
+}
+
+/// Runs `f` with the kernel's mapper and frame allocator.
+///
+/// Returns `None` if `init_kernel_memory` wasn't called yet. `f` must not touch
+/// lazily mapped memory (e.g. the heap), since resolving that fault needs the
+/// same lock.
+pub fn with_kernel_memory<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut KernelMemory) -> R,
+{
+    KERNEL_MEMORY.lock().as_mut().map(f)
+}
+
+// like with_kernel_memory, but gives up instead of spinning if the lock is taken
+// needed in fault handlers and the heap, where the interrupted code might hold the lock
+pub(crate) fn try_with_kernel_memory<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut KernelMemory) -> R,
+{
+    KERNEL_MEMORY.try_lock()?.as_mut().map(f)
+}
+
 /// Creates an example mapping for the given page to frame `0xb8000`.
 pub fn create_example_mapping(
     page: Page,
@@ -44,46 +209,164 @@ pub fn create_example_mapping(
     map_to_result.expect("map_to failed").flush();
 }
 
-pub struct EmptyFrameAllocator;
-
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.useable_frames().nth(self.next);
-        self.next += 1;
-        frame
+/// Maps a single page of any size to a newly allocated frame.
+///
+/// The frame goes back to the allocator if the page can't be mapped.
+/// Mapping `Size1GiB` pages requires CPU support, see `supports_1gib_pages`.
+pub fn map_page<S, M, A>(
+    page: Page<S>,
+    flags: PageTableFlags,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<PhysFrame<S>, MapToError<S>>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    A: FrameAllocator<S> + FrameAllocator<Size4KiB> + FrameDeallocator<S>,
+{
+    // whatever gets mapped has to be accounted for in the region manager
+    debug_assert!(
+        region::find(page.start_address()).is_some(),
+        "mapping {:?} outside of any reserved region",
+        page
+    );
+    let frame = FrameAllocator::<S>::allocate_frame(frame_allocator)
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(err) => {
+            // never made it into a page table, so nothing references it
+            unsafe { FrameDeallocator::<S>::deallocate_frame(frame_allocator, frame) };
+            return Err(err);
+        }
     }
+    Ok(frame)
 }
 
-// frame allocator that returns useable frames from bootloaders memory map
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+/// Unmaps a single page of any size and returns its frame to the allocator.
+pub fn unmap_page<S, M, D>(
+    page: Page<S>,
+    mapper: &mut M,
+    frame_deallocator: &mut D,
+) -> Result<(), UnmapError>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    D: FrameDeallocator<S>,
+{
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.flush();
+    // the page is gone, so nothing can reference the frame anymore
+    unsafe { frame_deallocator.deallocate_frame(frame) };
+    Ok(())
 }
 
-/// Create a FrameAllocator from the passed memory map.
+/// Maps `size` bytes starting at `start` to newly allocated frames.
 ///
-/// This function is unsafe because the caller must guarantee that the passed
-/// memory map is valid. The main requirement is that all frames that are marked
-/// as `USABLE` in it are really unused.
-impl BootInfoFrameAllocator {
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
+/// 2 MiB pages are used wherever the address is aligned and enough of the region
+/// is left; everything else is mapped with 4 KiB pages.
+pub fn map_region<M, A>(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    mapper: &mut M,
+    frame_allocator: &mut A,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB>,
+    A: FrameAllocator<Size4KiB>
+        + FrameAllocator<Size2MiB>
+        + FrameDeallocator<Size4KiB>
+        + FrameDeallocator<Size2MiB>,
+{
+    let end = start + size;
+    let mut addr = start.align_down(Size4KiB::SIZE);
+
+    while addr < end {
+        if addr.is_aligned(Size2MiB::SIZE) && end - addr >= Size2MiB::SIZE {
+            let page = Page::<Size2MiB>::containing_address(addr);
+            match map_page(page, flags, mapper, frame_allocator) {
+                Ok(_) => {
+                    addr += Size2MiB::SIZE;
+                    continue;
+                }
+                // no contiguous 2 MiB frame left, fall back to small pages
+                Err(MapToError::FrameAllocationFailed) => {}
+                Err(err) => return Err(small_page_error(err)),
+            }
         }
+        let page = Page::<Size4KiB>::containing_address(addr);
+        map_page(page, flags, mapper, frame_allocator)?;
+        addr += Size4KiB::SIZE;
     }
 
-    // returns an iterator over useable frames in specified memory map
-    fn useable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get useable regions
-        let regions = self.memory_map.iter();
-        let useable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = useable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform  to an iterator of start addresses
-        // By using flat_map instead of map, we get an Iterator<Item = u64> instead of an Iterator<Item = Iterator<Item = u64>>.
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // Create PhysFrame types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    Ok(())
+}
+
+/// Unmaps every page in `size` bytes starting at `start` and frees their frames,
+/// whatever page size they were mapped with. Unmapped holes are skipped.
+pub fn unmap_region<M, D>(
+    start: VirtAddr,
+    size: u64,
+    mapper: &mut M,
+    frame_deallocator: &mut D,
+) -> Result<(), UnmapError>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB> + Translate,
+    D: FrameDeallocator<Size4KiB> + FrameDeallocator<Size2MiB> + FrameDeallocator<Size1GiB>,
+{
+    let end = start + size;
+    let mut addr = start.align_down(Size4KiB::SIZE);
+
+    while addr < end {
+        match mapper.translate(addr) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                ..
+            } => {
+                let page = Page::<Size4KiB>::containing_address(addr);
+                unmap_page(page, mapper, frame_deallocator)?;
+                addr += Size4KiB::SIZE;
+            }
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(_),
+                ..
+            } => {
+                let page = Page::<Size2MiB>::containing_address(addr);
+                unmap_page(page, mapper, frame_deallocator)?;
+                addr = page.start_address() + Size2MiB::SIZE;
+            }
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size1GiB(_),
+                ..
+            } => {
+                let page = Page::<Size1GiB>::containing_address(addr);
+                unmap_page(page, mapper, frame_deallocator)?;
+                addr = page.start_address() + Size1GiB::SIZE;
+            }
+            TranslateResult::NotMapped => addr += Size4KiB::SIZE,
+            TranslateResult::InvalidFrameAddress(frame) => {
+                return Err(UnmapError::InvalidFrameAddress(frame))
+            }
+        }
     }
+
+    Ok(())
+}
+
+// report a failed 2 MiB mapping in terms of 4 KiB pages
+fn small_page_error(err: MapToError<Size2MiB>) -> MapToError<Size4KiB> {
+    match err {
+        MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+        MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+        MapToError::PageAlreadyMapped(frame) => {
+            MapToError::PageAlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+        }
+    }
+}
+
+/// Returns true if the CPU can map 1 GiB pages (CPUID `pdpe1gb`).
+pub fn supports_1gib_pages() -> bool {
+    let extended_features = unsafe { core::arch::x86_64::__cpuid(0x8000_0001) };
+    extended_features.edx & (1 << 26) != 0
 }
@@ -0,0 +1,282 @@
+use super::{cow, with_kernel_memory, KernelMemory};
+use core::ops::Range;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame,
+    Size1GiB, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Start of the part of the address space that is private to each address space.
+pub const USER_SPACE_START: u64 = 0x_1000_0000_0000;
+/// End of the part of the address space that is private to each address space.
+pub const USER_SPACE_END: u64 = 0x_4000_0000_0000;
+
+// level 4 entries covering the user space range
+// the bootloader loads the kernel into the lower half, so everything else is
+// shared, not only the upper half
+const USER_ENTRIES: Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    /// Kernel memory wasn't handed over via `init_kernel_memory` yet.
+    NotInitialized,
+    FrameAllocationFailed,
+}
+
+/// A page table hierarchy with its own user space range and the kernel mappings
+/// shared with every other address space.
+///
+/// Kernel mappings are shared at level 4: `init_kernel_memory` fills in every
+/// level 4 entry outside the user range up front, so later kernel mappings only
+/// ever change the shared lower level tables.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    physical_memory_offset: VirtAddr,
+}
+
+impl AddressSpace {
+    /// Creates a new address space with an empty user space range.
+    pub fn new() -> Result<Self, AddressSpaceError> {
+        with_kernel_memory(|memory| {
+            let level_4_frame = memory
+                .frame_allocator
+                .allocate_frame()
+                .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+            let physical_memory_offset = memory.mapper.phys_offset();
+            let kernel_table = memory.mapper.level_4_table();
+
+            let table = unsafe { &mut *table_ptr(physical_memory_offset, level_4_frame) };
+            table.zero();
+            for (i, entry) in kernel_table.iter().enumerate() {
+                if !USER_ENTRIES.contains(&i) {
+                    table[i] = entry.clone();
+                }
+            }
+
+            Ok(AddressSpace {
+                level_4_frame,
+                physical_memory_offset,
+            })
+        })
+        .unwrap_or(Err(AddressSpaceError::NotInitialized))
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns a mapper for this address space's page tables.
+    ///
+    /// Mappings outside `USER_SPACE_START..USER_SPACE_END` modify tables shared
+    /// with the kernel and therefore show up in every address space.
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe {
+            let table = &mut *table_ptr(self.physical_memory_offset, self.level_4_frame);
+            OffsetPageTable::new(table, self.physical_memory_offset)
+        }
+    }
+
+    /// Creates a copy of this address space that shares every user page
+    /// copy-on-write; the first write from either side gets a private copy.
+    ///
+    /// Huge pages are copied right away.
+    pub fn duplicate(&mut self) -> Result<AddressSpace, AddressSpaceError> {
+        let child = AddressSpace::new()?;
+        let physical_memory_offset = self.physical_memory_offset;
+        let parent_frame = self.level_4_frame;
+        let child_frame = child.level_4_frame;
+
+        // on error the partially copied child is dropped, which releases what it got
+        with_kernel_memory(|memory| unsafe {
+            let parent = &mut *table_ptr(physical_memory_offset, parent_frame);
+            let child = &mut *table_ptr(physical_memory_offset, child_frame);
+            for i in USER_ENTRIES {
+                if parent[i].flags().contains(PageTableFlags::PRESENT) {
+                    copy_table(
+                        memory,
+                        physical_memory_offset,
+                        &mut parent[i],
+                        &mut child[i],
+                        3,
+                    )?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap_or(Err(AddressSpaceError::NotInitialized))?;
+
+        // our own pages just became read-only
+        if self.is_active() {
+            tlb::flush_all();
+        }
+        Ok(child)
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Switches the CPU to this address space.
+    ///
+    /// Unsafe because the code and stack currently in use must be mapped in the
+    /// new address space, which holds as long as they are outside the user range.
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.level_4_frame, flags);
+    }
+}
+
+impl Drop for AddressSpace {
+    // frees every page table and frame mapped in the user range
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { activate_kernel_address_space() };
+        }
+
+        let physical_memory_offset = self.physical_memory_offset;
+        let level_4_frame = self.level_4_frame;
+        with_kernel_memory(|memory| unsafe {
+            let table = &mut *table_ptr(physical_memory_offset, level_4_frame);
+            for i in USER_ENTRIES {
+                let entry = &mut table[i];
+                if entry.flags().contains(PageTableFlags::PRESENT) {
+                    free_table(memory, physical_memory_offset, entry.addr(), 3);
+                }
+                entry.set_unused();
+            }
+            memory.frame_allocator.deallocate_frame(level_4_frame);
+        })
+        .expect("kernel memory not initialized");
+    }
+}
+
+/// Switches the CPU back to the kernel's own page tables.
+///
+/// Unsafe for the same reason as `AddressSpace::activate`.
+pub unsafe fn activate_kernel_address_space() {
+    let level_4_frame =
+        with_kernel_memory(|memory| memory.level_4_frame).expect("kernel memory not initialized");
+    let (_, flags) = Cr3::read();
+    Cr3::write(level_4_frame, flags);
+}
+
+// allocate an empty level 3 table for every unused level 4 entry outside the
+// user range, the entries are copied into each new address space after this
+pub(super) fn preallocate_kernel_tables(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let physical_memory_offset = mapper.phys_offset();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    for (i, entry) in mapper.level_4_table().iter_mut().enumerate() {
+        if USER_ENTRIES.contains(&i) || !entry.is_unused() {
+            continue;
+        }
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("no frames left for kernel page tables");
+        unsafe { (*table_ptr(physical_memory_offset, frame)).zero() };
+        entry.set_frame(frame, flags);
+    }
+}
+
+fn table_ptr(physical_memory_offset: VirtAddr, frame: PhysFrame) -> *mut PageTable {
+    (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+// copy the table `parent` points to on level `level` into a new table for `child`
+// leaf pages are shared copy-on-write instead of copied
+unsafe fn copy_table(
+    memory: &mut KernelMemory,
+    physical_memory_offset: VirtAddr,
+    parent: &mut PageTableEntry,
+    child: &mut PageTableEntry,
+    level: u8,
+) -> Result<(), AddressSpaceError> {
+    let child_frame: PhysFrame = memory
+        .frame_allocator
+        .allocate_frame()
+        .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+    let child_table = &mut *table_ptr(physical_memory_offset, child_frame);
+    child_table.zero();
+    // link the new table right away so dropping the child finds it
+    child.set_addr(child_frame.start_address(), parent.flags());
+
+    let parent_frame = PhysFrame::containing_address(parent.addr());
+    let parent_table = &mut *table_ptr(physical_memory_offset, parent_frame);
+    for (parent_entry, child_entry) in parent_table.iter_mut().zip(child_table.iter_mut()) {
+        let flags = parent_entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        match level {
+            1 => {
+                cow::share_entry(memory, parent_entry);
+                *child_entry = parent_entry.clone();
+            }
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => {
+                let frame = PhysFrame::<Size2MiB>::containing_address(parent_entry.addr());
+                let copy: PhysFrame<Size2MiB> = memory
+                    .frame_allocator
+                    .allocate_frame()
+                    .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+                cow::copy_frame(physical_memory_offset, frame, copy);
+                child_entry.set_addr(copy.start_address(), flags);
+            }
+            3 if flags.contains(PageTableFlags::HUGE_PAGE) => {
+                let frame = PhysFrame::<Size1GiB>::containing_address(parent_entry.addr());
+                let copy: PhysFrame<Size1GiB> = memory
+                    .frame_allocator
+                    .allocate_frame()
+                    .ok_or(AddressSpaceError::FrameAllocationFailed)?;
+                cow::copy_frame(physical_memory_offset, frame, copy);
+                child_entry.set_addr(copy.start_address(), flags);
+            }
+            _ => copy_table(
+                memory,
+                physical_memory_offset,
+                parent_entry,
+                child_entry,
+                level - 1,
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+// free the page table at `table_addr` on level `level` with all mapped frames below it
+unsafe fn free_table(
+    memory: &mut KernelMemory,
+    physical_memory_offset: VirtAddr,
+    table_addr: PhysAddr,
+    level: u8,
+) {
+    let table_frame = PhysFrame::containing_address(table_addr);
+    let table = &mut *table_ptr(physical_memory_offset, table_frame);
+
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let addr = entry.addr();
+        match level {
+            1 => cow::release_frame(memory, PhysFrame::<Size4KiB>::containing_address(addr)),
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => memory
+                .frame_allocator
+                .deallocate_frame(PhysFrame::<Size2MiB>::containing_address(addr)),
+            3 if flags.contains(PageTableFlags::HUGE_PAGE) => memory
+                .frame_allocator
+                .deallocate_frame(PhysFrame::<Size1GiB>::containing_address(addr)),
+            _ => free_table(memory, physical_memory_offset, addr, level - 1),
+        }
+        entry.set_unused();
+    }
+
+    memory.frame_allocator.deallocate_frame(table_frame);
+}
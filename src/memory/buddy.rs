@@ -0,0 +1,198 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: u64 = 4096;
+
+/// Largest block order, 2^18 frames = 1 GiB.
+pub const MAX_ORDER: usize = 18;
+
+// header written into the first frame of every free block
+struct FreeBlock {
+    next: Option<PhysAddr>,
+}
+
+// buddy allocator that hands out naturally aligned runs of 2^order frames
+// free lists are stored inside the free frames themselves, so no heap is needed
+pub struct BuddyFrameAllocator {
+    free_lists: [Option<PhysAddr>; MAX_ORDER + 1],
+    physical_memory_offset: VirtAddr,
+    free_frames: usize,
+    // end of the highest useable region
+    max_address: PhysAddr,
+}
+
+/// Smallest order whose block holds at least `size` bytes.
+pub fn order_for_size(size: u64) -> usize {
+    let frames = (size + FRAME_SIZE - 1) / FRAME_SIZE;
+    frames.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+// order of a single frame of page size S
+fn page_order<S: PageSize>() -> usize {
+    (S::SIZE / FRAME_SIZE).trailing_zeros() as usize
+}
+
+impl BuddyFrameAllocator {
+    /// Create a BuddyFrameAllocator from the passed memory map.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid and that the complete physical memory is mapped at
+    /// `physical_memory_offset`. All frames marked as `USABLE` must really be unused.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let mut allocator = BuddyFrameAllocator {
+            free_lists: [None; MAX_ORDER + 1],
+            physical_memory_offset,
+            free_frames: 0,
+            max_address: PhysAddr::new(0),
+        };
+
+        let useable_regions = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+        for region in useable_regions {
+            // split region into the largest aligned blocks that fit
+            let mut start = region.range.start_addr();
+            let end = region.range.end_addr();
+            allocator.max_address = allocator.max_address.max(PhysAddr::new(end));
+            while start < end {
+                let order = (0..=MAX_ORDER)
+                    .rev()
+                    .find(|&o| start % block_size(o) == 0 && start + block_size(o) <= end)
+                    .unwrap();
+                allocator.free_block(PhysAddr::new(start), order);
+                start += block_size(order);
+            }
+        }
+
+        allocator
+    }
+
+    /// Number of 4 KiB frames that can still be allocated.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// End of the highest useable physical memory region.
+    pub fn max_address(&self) -> PhysAddr {
+        self.max_address
+    }
+
+    /// Allocates `2^order` physically contiguous frames aligned to their combined
+    /// size, with the whole run lying below `limit` (e.g. 4 GiB for 32 bit DMA).
+    pub fn allocate_contiguous(&mut self, order: usize, limit: PhysAddr) -> Option<PhysFrameRange> {
+        let start = self.allocate_block(order, limit.as_u64())?;
+        let start_frame = PhysFrame::containing_address(start);
+        let end_frame = PhysFrame::containing_address(start + block_size(order));
+        Some(PhysFrame::range(start_frame, end_frame))
+    }
+
+    /// Returns a run previously handed out by `allocate_contiguous`.
+    ///
+    /// Unsafe because the caller must guarantee that the frames are no longer in use.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        let frames = range.end - range.start;
+        assert!(frames.is_power_of_two(), "frame run is not a power of two");
+        let order = frames.trailing_zeros() as usize;
+        self.free_block(range.start.start_address(), order);
+    }
+
+    fn allocate_block(&mut self, order: usize, limit: u64) -> Option<PhysAddr> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let size = block_size(order);
+
+        // find the smallest block whose lower part fits below limit
+        for current in order..=MAX_ORDER {
+            if let Some(addr) = self.take_block(current, |addr| addr.as_u64() + size <= limit) {
+                // split off upper halves until the block has the requested order
+                for lower in (order..current).rev() {
+                    unsafe { self.push(lower, addr + block_size(lower)) };
+                }
+                self.free_frames -= 1 << order;
+                return Some(addr);
+            }
+        }
+
+        None
+    }
+
+    // add block to free lists, merging it with its buddy as long as possible
+    unsafe fn free_block(&mut self, mut addr: PhysAddr, mut order: usize) {
+        self.free_frames += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = PhysAddr::new(addr.as_u64() ^ block_size(order));
+            if self.take_block(order, |a| a == buddy).is_none() {
+                break;
+            }
+            addr = PhysAddr::new(addr.as_u64().min(buddy.as_u64()));
+            order += 1;
+        }
+        self.push(order, addr);
+    }
+
+    unsafe fn push(&mut self, order: usize, addr: PhysAddr) {
+        let node = FreeBlock {
+            next: self.free_lists[order].take(),
+        };
+        self.node_ptr(addr).write(node);
+        self.free_lists[order] = Some(addr);
+    }
+
+    // remove first block from list `order` matching predicate
+    fn take_block(
+        &mut self,
+        order: usize,
+        predicate: impl Fn(PhysAddr) -> bool,
+    ) -> Option<PhysAddr> {
+        let mut previous: Option<PhysAddr> = None;
+        let mut current = self.free_lists[order];
+
+        while let Some(addr) = current {
+            let next = unsafe { (*self.node_ptr(addr)).next };
+            if predicate(addr) {
+                match previous {
+                    Some(prev) => unsafe { (*self.node_ptr(prev)).next = next },
+                    None => self.free_lists[order] = next,
+                }
+                return Some(addr);
+            }
+            previous = current;
+            current = next;
+        }
+
+        None
+    }
+
+    fn node_ptr(&self, addr: PhysAddr) -> *mut FreeBlock {
+        (self.physical_memory_offset + addr.as_u64()).as_mut_ptr()
+    }
+}
+
+// buddy blocks are naturally aligned, so huge frames are just blocks of a higher order
+macro_rules! impl_frame_allocator {
+    ($($size:ty),*) => {$(
+        unsafe impl FrameAllocator<$size> for BuddyFrameAllocator {
+            fn allocate_frame(&mut self) -> Option<PhysFrame<$size>> {
+                self.allocate_block(page_order::<$size>(), u64::MAX)
+                    .map(PhysFrame::containing_address)
+            }
+        }
+
+        impl FrameDeallocator<$size> for BuddyFrameAllocator {
+            unsafe fn deallocate_frame(&mut self, frame: PhysFrame<$size>) {
+                self.free_block(frame.start_address(), page_order::<$size>());
+            }
+        }
+    )*};
+}
+
+impl_frame_allocator!(Size4KiB, Size2MiB, Size1GiB);
@@ -0,0 +1,178 @@
+use super::address_space::{USER_SPACE_END, USER_SPACE_START};
+use super::buddy::{self, BuddyFrameAllocator};
+use super::{try_with_kernel_memory, KernelMemory};
+use core::slice;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Marks a page that was writable before it got shared copy-on-write.
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+// number of address spaces sharing each physical frame
+// 0 means the frame has a single owner and isn't tracked
+// stored in frames from the buddy allocator, so the page fault handler never
+// needs the heap
+pub(crate) struct FrameRefCounts {
+    counts: &'static mut [u16],
+}
+
+impl FrameRefCounts {
+    pub(crate) fn new(
+        frame_allocator: &mut BuddyFrameAllocator,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let frames = (frame_allocator.max_address().as_u64() / Size4KiB::SIZE) as usize;
+        let size = (frames * 2) as u64;
+        let limit = frame_allocator.max_address();
+        let range = frame_allocator
+            .allocate_contiguous(buddy::order_for_size(size), limit)
+            .expect("no memory for frame reference counts");
+
+        let ptr: *mut u16 =
+            (physical_memory_offset + range.start.start_address().as_u64()).as_mut_ptr();
+        let counts = unsafe { slice::from_raw_parts_mut(ptr, frames) };
+        counts.fill(0);
+        FrameRefCounts { counts }
+    }
+
+    fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / Size4KiB::SIZE) as usize
+    }
+
+    fn get(&self, frame: PhysFrame) -> u16 {
+        self.counts.get(Self::index(frame)).copied().unwrap_or(0)
+    }
+
+    // one more address space maps the frame
+    fn share(&mut self, frame: PhysFrame) {
+        if let Some(count) = self.counts.get_mut(Self::index(frame)) {
+            *count = (*count).max(1) + 1;
+        }
+    }
+
+    // one address space stops mapping the frame
+    // returns true if that was the last one, so the frame can be freed
+    fn release(&mut self, frame: PhysFrame) -> bool {
+        match self.counts.get_mut(Self::index(frame)) {
+            Some(0) => true,
+            Some(count) => {
+                *count -= 1;
+                if *count == 1 {
+                    *count = 0;
+                }
+                false
+            }
+            // beyond useable memory, e.g. a device frame, never ours to free
+            None => false,
+        }
+    }
+}
+
+/// Makes a writable user page entry copy-on-write and counts the new sharer.
+///
+/// Entries that are already read-only are shared as they are.
+pub(crate) fn share_entry(memory: &mut KernelMemory, entry: &mut PageTableEntry) {
+    let mut flags = entry.flags();
+    if flags.contains(PageTableFlags::WRITABLE) {
+        flags.remove(PageTableFlags::WRITABLE);
+        flags.insert(COW);
+        entry.set_flags(flags);
+    }
+    memory
+        .frame_refs
+        .share(PhysFrame::containing_address(entry.addr()));
+}
+
+/// Drops one reference to a user frame, freeing it once nobody maps it anymore.
+pub(crate) fn release_frame(memory: &mut KernelMemory, frame: PhysFrame) {
+    if memory.frame_refs.release(frame) {
+        unsafe { memory.frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+/// Tries to resolve a write to a copy-on-write page in the active address space.
+///
+/// Returns false if the fault has to be treated as a real error.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let cow_fault = PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION;
+    if !error_code.contains(cow_fault) {
+        return false;
+    }
+    if addr.as_u64() < USER_SPACE_START || addr.as_u64() >= USER_SPACE_END {
+        return false;
+    }
+
+    try_with_kernel_memory(|memory| {
+        let physical_memory_offset = memory.mapper.phys_offset();
+        let entry = match unsafe { leaf_entry(physical_memory_offset, addr) } {
+            Some(entry) if entry.flags().contains(COW) => entry,
+            _ => return false,
+        };
+        let frame = PhysFrame::containing_address(entry.addr());
+        let mut flags = entry.flags();
+        flags.remove(COW);
+        flags.insert(PageTableFlags::WRITABLE);
+
+        if memory.frame_refs.get(frame) == 0 {
+            // every other sharer already made its own copy
+            entry.set_flags(flags);
+        } else {
+            let copy: PhysFrame = match memory.frame_allocator.allocate_frame() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            unsafe { copy_frame(physical_memory_offset, frame, copy) };
+            entry.set_addr(copy.start_address(), flags);
+            memory.frame_refs.release(frame);
+        }
+
+        tlb::flush(addr);
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// Copies the contents of frame `from` into frame `to`.
+pub(crate) unsafe fn copy_frame<S: PageSize>(
+    physical_memory_offset: VirtAddr,
+    from: PhysFrame<S>,
+    to: PhysFrame<S>,
+) {
+    let src: *const u8 = (physical_memory_offset + from.start_address().as_u64()).as_ptr();
+    let dst: *mut u8 = (physical_memory_offset + to.start_address().as_u64()).as_mut_ptr();
+    core::ptr::copy_nonoverlapping(src, dst, S::SIZE as usize);
+}
+
+// level 1 entry mapping `addr` in the active address space, if it is mapped with 4 KiB pages
+unsafe fn leaf_entry(
+    physical_memory_offset: VirtAddr,
+    addr: VirtAddr,
+) -> Option<&'static mut PageTableEntry> {
+    let table_at = |addr: PhysAddr| -> &'static mut PageTable {
+        &mut *(physical_memory_offset + addr.as_u64()).as_mut_ptr()
+    };
+
+    let mut table = table_at(Cr3::read().0.start_address());
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index()];
+    for index in indexes {
+        let entry = &table[index];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = table_at(entry.addr());
+    }
+
+    let entry = &mut table[addr.p1_index()];
+    if entry.flags().contains(PageTableFlags::PRESENT) {
+        Some(entry)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,58 @@
+// reads the kernel's own ELF program headers, which the linker places at the
+// start of the first loadable segment
+
+// program header type and flags
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+
+extern "C" {
+    // start of the ELF header, the linker puts it at the beginning of the first segment
+    static __ehdr_start: u8;
+}
+
+// ELF64 program header, only some fields are used
+#[allow(dead_code)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl ProgramHeader {
+    // segments don't have to start on a page boundary, so check for overlap
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        start < self.p_vaddr + self.p_memsz && self.p_vaddr < end
+    }
+}
+
+/// Loadable segments of the kernel image.
+pub fn kernel_segments() -> impl Iterator<Item = &'static ProgramHeader> {
+    // the headers are part of the loaded image and never change
+    unsafe {
+        let ehdr = &__ehdr_start as *const u8;
+        let phoff = *(ehdr.add(32) as *const u64);
+        let phentsize = *(ehdr.add(54) as *const u16) as usize;
+        let phnum = *(ehdr.add(56) as *const u16) as usize;
+
+        (0..phnum)
+            .map(move |i| &*(ehdr.add(phoff as usize + i * phentsize) as *const ProgramHeader))
+            .filter(|header| header.p_type == PT_LOAD)
+    }
+}
+
+/// Page aligned range `(start, end)` covering every loadable segment.
+pub fn kernel_image() -> (u64, u64) {
+    let start = kernel_segments().map(|s| s.p_vaddr).min().unwrap_or(0);
+    let end = kernel_segments()
+        .map(|s| s.p_vaddr + s.p_memsz)
+        .max()
+        .unwrap_or(0);
+    (start & !0xfff, (end + 0xfff) & !0xfff)
+}
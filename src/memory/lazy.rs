@@ -0,0 +1,100 @@
+use super::try_with_kernel_memory;
+use spin::Mutex;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+const MAX_LAZY_REGIONS: usize = 16;
+
+// virtual range whose pages are only mapped once they are first touched
+#[derive(Debug, Clone, Copy)]
+struct LazyRegion {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: PageTableFlags,
+}
+
+static LAZY_REGIONS: Mutex<[Option<LazyRegion>; MAX_LAZY_REGIONS]> =
+    Mutex::new([None; MAX_LAZY_REGIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LazyRegionError {
+    Overlap,
+    TooManyRegions,
+}
+
+/// Registers `size` bytes starting at `start` as demand paged.
+///
+/// Page faults on not-present pages inside the region are resolved by mapping
+/// a zeroed frame with the given flags.
+pub fn register(start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), LazyRegionError> {
+    let new = LazyRegion {
+        start,
+        end: start + size,
+        flags,
+    };
+    let mut regions = LAZY_REGIONS.lock();
+
+    if regions
+        .iter()
+        .flatten()
+        .any(|r| new.start < r.end && r.start < new.end)
+    {
+        return Err(LazyRegionError::Overlap);
+    }
+
+    let slot = regions
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(LazyRegionError::TooManyRegions)?;
+    *slot = Some(new);
+    Ok(())
+}
+
+/// Tries to resolve a page fault by mapping the page if it lies in a lazy region.
+///
+/// Returns false if the fault has to be treated as a real error.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    // page is present, so this is an access violation and nothing we can fix
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    let region = match LAZY_REGIONS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|r| r.start <= addr && addr < r.end)
+    {
+        Some(region) => *region,
+        None => return false,
+    };
+
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    try_with_kernel_memory(|memory| {
+        let frame = match memory.frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let map_to_result = unsafe {
+            memory
+                .mapper
+                .map_to(page, frame, region.flags, &mut memory.frame_allocator)
+        };
+        match map_to_result {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { memory.frame_allocator.deallocate_frame(frame) };
+                return false;
+            }
+        }
+
+        // don't leak old contents of the frame
+        let page_ptr: *mut u8 = page.start_address().as_mut_ptr();
+        unsafe { page_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+        true
+    })
+    .unwrap_or(false)
+}
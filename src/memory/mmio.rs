@@ -0,0 +1,204 @@
+use super::region::{self, RegionError, RegionKind};
+use super::with_kernel_memory;
+use core::ptr;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const IA32_PAT: u32 = 0x277;
+
+// PAT memory types
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WT: u64 = 0x04;
+const PAT_WB: u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+// same as the power-on default, except entry 1 (selected by WRITE_THROUGH alone)
+// is write-combining instead of write-through, which moves to entry 5
+const PAT_LAYOUT: [u64; 8] = [
+    PAT_WB,
+    PAT_WC,
+    PAT_UC_MINUS,
+    PAT_UC,
+    PAT_WB,
+    PAT_WT,
+    PAT_UC_MINUS,
+    PAT_UC,
+];
+
+static PAT_INIT: spin::Once<()> = spin::Once::new();
+
+/// Caching behaviour of an MMIO mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Normal cached memory, e.g. for memory shared with a device through RAM.
+    WriteBack,
+    /// Strongly uncached, required for device registers.
+    Uncached,
+    /// Uncached but writes may be combined, e.g. for framebuffers.
+    WriteCombining,
+}
+
+impl CacheMode {
+    fn flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            CacheMode::WriteCombining => PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MmioError {
+    /// Kernel memory wasn't handed over via `init_kernel_memory` yet.
+    NotInitialized,
+    /// Requested a mapping of zero bytes.
+    ZeroSize,
+    Region(RegionError),
+    Map(MapToError<Size4KiB>),
+}
+
+/// A physical range mapped into kernel virtual memory by `ioremap`.
+///
+/// The mapping is removed when the handle is dropped.
+#[derive(Debug)]
+pub struct MmioRegion {
+    region_start: VirtAddr,
+    virt_addr: VirtAddr,
+    phys_addr: PhysAddr,
+    size: u64,
+}
+
+impl MmioRegion {
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt_addr
+    }
+
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns a pointer to a `T` at `offset` bytes into the region.
+    pub fn as_ptr<T>(&self, offset: u64) -> *mut T {
+        assert!(
+            offset + core::mem::size_of::<T>() as u64 <= self.size,
+            "MMIO access out of bounds"
+        );
+        (self.virt_addr + offset).as_mut_ptr()
+    }
+
+    /// Volatile read of a `T` at `offset` bytes into the region.
+    pub fn read<T: Copy>(&self, offset: u64) -> T {
+        unsafe { ptr::read_volatile(self.as_ptr(offset)) }
+    }
+
+    /// Volatile write of a `T` at `offset` bytes into the region.
+    pub fn write<T: Copy>(&self, offset: u64, value: T) {
+        unsafe { ptr::write_volatile(self.as_ptr(offset), value) }
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        let pages = pages(self.region_start, self.virt_addr + self.size);
+        with_kernel_memory(|memory| {
+            for page in pages {
+                // the frames belong to the device, so they are not deallocated
+                if let Ok((_, flush)) = memory.mapper.unmap(page) {
+                    flush.flush();
+                }
+            }
+        });
+        region::release(self.region_start).ok();
+    }
+}
+
+/// Maps `size` bytes of physical memory starting at `phys_addr` into kernel
+/// virtual memory with the given cache mode.
+///
+/// Unsafe because the caller must make sure that the physical range is device
+/// memory (or otherwise not in use by anything else), since it becomes writable.
+pub unsafe fn ioremap(
+    phys_addr: PhysAddr,
+    size: u64,
+    mode: CacheMode,
+) -> Result<MmioRegion, MmioError> {
+    if size == 0 {
+        return Err(MmioError::ZeroSize);
+    }
+    PAT_INIT.call_once(init_pat);
+
+    let phys_start = phys_addr.align_down(Size4KiB::SIZE);
+    let offset = phys_addr - phys_start;
+    let map_size = (offset + size + Size4KiB::SIZE - 1) & !(Size4KiB::SIZE - 1);
+
+    let region =
+        region::allocate(map_size, Size4KiB::SIZE, RegionKind::Mmio).map_err(MmioError::Region)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | mode.flags();
+
+    let result = with_kernel_memory(|memory| {
+        for (i, page) in pages(region.start, region.end()).enumerate() {
+            let frame = PhysFrame::containing_address(phys_start + i as u64 * Size4KiB::SIZE);
+            let map_to_result =
+                memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut memory.frame_allocator);
+            match map_to_result {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    // undo the pages mapped so far
+                    for page in pages(region.start, page.start_address()) {
+                        if let Ok((_, flush)) = memory.mapper.unmap(page) {
+                            flush.flush();
+                        }
+                    }
+                    return Err(MmioError::Map(err));
+                }
+            }
+        }
+        Ok(())
+    })
+    .unwrap_or(Err(MmioError::NotInitialized));
+
+    if let Err(err) = result {
+        region::release(region.start).ok();
+        return Err(err);
+    }
+
+    Ok(MmioRegion {
+        region_start: region.start,
+        virt_addr: region.start + offset,
+        phys_addr,
+        size,
+    })
+}
+
+fn pages(start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = Page<Size4KiB>> {
+    let start = Page::containing_address(start);
+    let end = Page::containing_address(end - 1u64);
+    Page::range_inclusive(start, end)
+}
+
+// program the page attribute table so WRITE_THROUGH alone selects write-combining
+fn init_pat() {
+    let pat = PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |pat, (i, &memory_type)| pat | memory_type << (i * 8));
+    unsafe {
+        // caches have to be flushed so no line is held with its old memory type
+        core::arch::asm!("wbinvd", options(nostack));
+        Msr::new(IA32_PAT).write(pat);
+        core::arch::asm!("wbinvd", options(nostack));
+    }
+}
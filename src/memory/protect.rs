@@ -0,0 +1,82 @@
+use super::elf::{kernel_segments, PF_W, PF_X};
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// Enables no-execute support and applies W^X to every existing mapping.
+///
+/// Kernel text becomes read-only and executable, read-only data non-executable
+/// and everything writable (data, stacks, the physical memory map, ...) is marked
+/// `NO_EXECUTE`. Also turns on write protection for supervisor accesses, and
+/// SMEP/SMAP if the CPU supports them.
+///
+/// Unsafe because the complete physical memory must be mapped at
+/// `physical_memory_offset`.
+pub unsafe fn init(physical_memory_offset: VirtAddr) {
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+
+    let (smep, smap) = smep_smap_support();
+    if smep {
+        Cr4::update(|flags| flags.insert(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION));
+    }
+    if smap {
+        Cr4::update(|flags| flags.insert(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION));
+    }
+
+    let level_4_frame = Cr3::read().0;
+    let table =
+        &mut *(physical_memory_offset + level_4_frame.start_address().as_u64()).as_mut_ptr();
+    protect_table(table, 4, 0, physical_memory_offset);
+    tlb::flush_all();
+}
+
+fn smep_smap_support() -> (bool, bool) {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 7 {
+        return (false, false);
+    }
+    let features = unsafe { __cpuid_count(7, 0) };
+    (features.ebx & (1 << 7) != 0, features.ebx & (1 << 20) != 0)
+}
+
+// apply W^X to every leaf below `table`, which maps the range starting at `start`
+unsafe fn protect_table(
+    table: &mut PageTable,
+    level: u8,
+    start: u64,
+    physical_memory_offset: VirtAddr,
+) {
+    let entry_size = 1u64 << (12 + 9 * (level as u64 - 1));
+    for (i, entry) in table.iter_mut().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let addr = VirtAddr::new_truncate(start + i as u64 * entry_size).as_u64();
+
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            entry.set_flags(leaf_flags(flags, addr, addr + entry_size));
+        } else {
+            let table = &mut *(physical_memory_offset + entry.addr().as_u64()).as_mut_ptr();
+            protect_table(table, level - 1, addr, physical_memory_offset);
+        }
+    }
+}
+
+fn leaf_flags(mut flags: PageTableFlags, start: u64, end: u64) -> PageTableFlags {
+    let segment = kernel_segments().find(|segment| segment.overlaps(start, end));
+    match segment {
+        Some(segment) => {
+            flags.set(PageTableFlags::WRITABLE, segment.p_flags & PF_W != 0);
+            flags.set(PageTableFlags::NO_EXECUTE, segment.p_flags & PF_X == 0);
+        }
+        // not part of the kernel image, so nothing in it is meant to run
+        None => flags.insert(PageTableFlags::NO_EXECUTE),
+    }
+    flags
+}
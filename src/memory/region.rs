@@ -0,0 +1,203 @@
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+const MAX_REGIONS: usize = 64;
+
+/// Start of the window `allocate` hands out ranges from.
+pub const DYNAMIC_START: u64 = 0x_5000_0000_0000;
+/// End of the window `allocate` hands out ranges from.
+pub const DYNAMIC_END: u64 = 0x_6000_0000_0000;
+
+static REGIONS: Mutex<RegionManager> = Mutex::new(RegionManager::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    Stack,
+    Mmio,
+    PhysicalMap,
+    /// The loaded kernel image, from its ELF program headers.
+    Kernel,
+    /// Whatever else the bootloader left mapped: its stack, the boot info, ...
+    Boot,
+    /// Per address space range, see `address_space::USER_SPACE_START`.
+    User,
+    /// Scratch memory of an `allocator::arena::Arena`.
+    Arena,
+    Other,
+}
+
+/// A reserved range of kernel virtual address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtRegion {
+    pub start: VirtAddr,
+    pub size: u64,
+    pub kind: RegionKind,
+}
+
+impl VirtRegion {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        start < self.end() && self.start < end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// Requested range overlaps an already reserved region.
+    Overlap(VirtRegion),
+    /// No free range of the requested size left in the dynamic window.
+    OutOfSpace,
+    TooManyRegions,
+    NotFound,
+}
+
+// keeps track of which parts of the virtual address space are in use
+// fixed size table, because regions are reserved before the heap exists
+pub struct RegionManager {
+    regions: [Option<VirtRegion>; MAX_REGIONS],
+}
+
+impl RegionManager {
+    pub const fn new() -> Self {
+        RegionManager {
+            regions: [None; MAX_REGIONS],
+        }
+    }
+
+    /// Reserves the given fixed range, failing if any part of it is already taken.
+    pub fn reserve(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        kind: RegionKind,
+    ) -> Result<VirtRegion, RegionError> {
+        if let Some(existing) = self.overlapping(start, start + size) {
+            return Err(RegionError::Overlap(existing));
+        }
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(RegionError::TooManyRegions)?;
+
+        let region = VirtRegion { start, size, kind };
+        *slot = Some(region);
+        Ok(region)
+    }
+
+    /// Finds and reserves a free range of `size` bytes aligned to `align` in the
+    /// dynamic window.
+    pub fn allocate(
+        &mut self,
+        size: u64,
+        align: u64,
+        kind: RegionKind,
+    ) -> Result<VirtRegion, RegionError> {
+        let mut start = VirtAddr::new(DYNAMIC_START).align_up(align);
+
+        // first fit: skip past every region in the way until the range is free
+        loop {
+            let end = start + size;
+            if end.as_u64() > DYNAMIC_END {
+                return Err(RegionError::OutOfSpace);
+            }
+            match self.overlapping(start, end) {
+                Some(existing) => start = existing.end().align_up(align),
+                None => return self.reserve(start, size, kind),
+            }
+        }
+    }
+
+    /// Releases the region starting at `start`.
+    pub fn release(&mut self, start: VirtAddr) -> Result<VirtRegion, RegionError> {
+        self.regions
+            .iter_mut()
+            .find(|r| matches!(r, Some(region) if region.start == start))
+            .and_then(|r| r.take())
+            .ok_or(RegionError::NotFound)
+    }
+
+    /// Returns the region containing `addr`, if any.
+    pub fn find(&self, addr: VirtAddr) -> Option<VirtRegion> {
+        self.iter().find(|r| r.contains(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VirtRegion> + '_ {
+        self.regions.iter().flatten().copied()
+    }
+
+    pub fn overlapping(&self, start: VirtAddr, end: VirtAddr) -> Option<VirtRegion> {
+        self.iter().find(|r| r.overlaps(start, end))
+    }
+}
+
+/// Reserves a fixed range in the kernel's region manager.
+pub fn reserve(start: VirtAddr, size: u64, kind: RegionKind) -> Result<VirtRegion, RegionError> {
+    REGIONS.lock().reserve(start, size, kind)
+}
+
+/// Allocates a free range from the kernel's region manager.
+pub fn allocate(size: u64, align: u64, kind: RegionKind) -> Result<VirtRegion, RegionError> {
+    REGIONS.lock().allocate(size, align, kind)
+}
+
+/// Releases a range previously reserved or allocated.
+pub fn release(start: VirtAddr) -> Result<VirtRegion, RegionError> {
+    REGIONS.lock().release(start)
+}
+
+/// Returns the region containing `addr`, if any.
+pub fn find(addr: VirtAddr) -> Option<VirtRegion> {
+    REGIONS.lock().find(addr)
+}
+
+/// Returns a region overlapping the given range, if any.
+pub fn find_overlapping(start: VirtAddr, size: u64) -> Option<VirtRegion> {
+    REGIONS.lock().overlapping(start, start + size)
+}
+
+// for fault handlers, which can't wait for a lock the interrupted code may hold
+pub(crate) fn try_find(addr: VirtAddr) -> Option<VirtRegion> {
+    REGIONS.try_lock()?.find(addr)
+}
+
+/// Runs `f` for every reserved region.
+pub fn for_each(mut f: impl FnMut(VirtRegion)) {
+    REGIONS.lock().iter().for_each(&mut f);
+}
+
+#[test_case]
+fn test_reserve_rejects_overlap() {
+    let mut manager = RegionManager::new();
+    let start = VirtAddr::new(0x_1000_0000);
+    manager.reserve(start, 0x2000, RegionKind::Other).unwrap();
+    assert!(matches!(
+        manager.reserve(start + 0x1000u64, 0x2000, RegionKind::Other),
+        Err(RegionError::Overlap(_))
+    ));
+    // directly adjacent is fine
+    manager
+        .reserve(start + 0x2000u64, 0x1000, RegionKind::Other)
+        .unwrap();
+}
+
+#[test_case]
+fn test_allocate_skips_used_ranges() {
+    let mut manager = RegionManager::new();
+    manager
+        .reserve(VirtAddr::new(DYNAMIC_START), 0x1800, RegionKind::Heap)
+        .unwrap();
+    let region = manager.allocate(0x1000, 0x1000, RegionKind::Stack).unwrap();
+    assert_eq!(region.start, VirtAddr::new(DYNAMIC_START + 0x2000));
+    assert_eq!(manager.find(region.start + 0x10u64), Some(region));
+    manager.release(region.start).unwrap();
+    assert_eq!(manager.find(region.start), None);
+}
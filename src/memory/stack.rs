@@ -0,0 +1,110 @@
+use super::region::{self, RegionError, RegionKind};
+use super::with_kernel_memory;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+#[derive(Debug)]
+pub enum StackError {
+    /// Kernel memory wasn't handed over via `init_kernel_memory` yet.
+    NotInitialized,
+    Region(RegionError),
+    Map(MapToError<Size4KiB>),
+}
+
+/// A kernel stack with an unmapped guard page directly below it, so running
+/// off its end faults instead of corrupting whatever comes next.
+///
+/// The stack is unmapped and its frames freed when it is dropped.
+#[derive(Debug)]
+pub struct KernelStack {
+    // start of the guard page
+    region_start: VirtAddr,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// Highest address of the stack, the initial stack pointer.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// Lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.region_start + PAGE_SIZE
+    }
+
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(self.region_start)
+    }
+
+    /// Gives up ownership of the stack, leaving it mapped forever.
+    pub fn leak(self) -> VirtAddr {
+        let top = self.top;
+        core::mem::forget(self);
+        top
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let size = self.top - self.bottom();
+        with_kernel_memory(|memory| {
+            let mapper = &mut memory.mapper;
+            let frame_allocator = &mut memory.frame_allocator;
+            super::unmap_region(self.bottom(), size, mapper, frame_allocator).ok();
+        });
+        region::release(self.region_start).ok();
+    }
+}
+
+/// Allocates a kernel stack of `pages` 4 KiB pages from the stack region.
+pub fn allocate(pages: u64) -> Result<KernelStack, StackError> {
+    // one extra page for the guard, which is reserved but never mapped
+    let region = region::allocate((pages + 1) * PAGE_SIZE, PAGE_SIZE, RegionKind::Stack)
+        .map_err(StackError::Region)?;
+    let bottom = region.start + PAGE_SIZE;
+    let size = pages * PAGE_SIZE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    let result = with_kernel_memory(|memory| {
+        let mapper = &mut memory.mapper;
+        let frame_allocator = &mut memory.frame_allocator;
+        // 4 KiB pages only, the guard page has to stay unmapped
+        let pages = Page::range(
+            Page::containing_address(bottom),
+            Page::containing_address(bottom + size),
+        );
+        for page in pages {
+            if let Err(err) = super::map_page(page, flags, mapper, frame_allocator) {
+                super::unmap_region(bottom, size, mapper, frame_allocator).ok();
+                return Err(StackError::Map(err));
+            }
+        }
+        Ok(())
+    })
+    .unwrap_or(Err(StackError::NotInitialized));
+
+    if let Err(err) = result {
+        region::release(region.start).ok();
+        return Err(err);
+    }
+
+    Ok(KernelStack {
+        region_start: region.start,
+        top: bottom + size,
+    })
+}
+
+/// Returns true if `addr` lies in the guard page of a kernel stack.
+///
+/// Meant for fault handlers, gives up instead of waiting if the region manager
+/// is locked.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    match region::try_find(addr) {
+        Some(region) => region.kind == RegionKind::Stack && addr < region.start + PAGE_SIZE,
+        None => false,
+    }
+}
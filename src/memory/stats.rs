@@ -0,0 +1,103 @@
+use super::with_kernel_memory;
+use crate::println;
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use core::fmt;
+use spin::Mutex;
+
+// memory map the bootloader handed to the kernel, recorded for diagnostics
+static MEMORY_MAP: Mutex<Option<&'static MemoryMap>> = Mutex::new(None);
+
+/// Frame counts of the physical memory, in 4 KiB frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Frames covered by any region of the memory map.
+    pub total_frames: u64,
+    /// Frames marked usable by the bootloader.
+    pub usable_frames: u64,
+    /// Usable frames currently handed out by the frame allocator.
+    pub used_frames: u64,
+    /// Frames that were never usable (firmware, kernel image, page tables, ...).
+    pub reserved_frames: u64,
+}
+
+impl MemoryStats {
+    /// Computes the stats for the given memory map regions, with `free_frames`
+    /// usable frames still left in the frame allocator.
+    pub fn from_regions(regions: &[MemoryRegion], free_frames: u64) -> Self {
+        let total_frames: u64 = regions
+            .iter()
+            .filter(|r| r.region_type != MemoryRegionType::Empty)
+            .map(region_frames)
+            .sum();
+        let usable_frames: u64 = regions
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(region_frames)
+            .sum();
+
+        MemoryStats {
+            total_frames,
+            usable_frames,
+            used_frames: usable_frames.saturating_sub(free_frames),
+            reserved_frames: total_frames - usable_frames,
+        }
+    }
+
+    pub fn free_frames(&self) -> u64 {
+        self.usable_frames - self.used_frames
+    }
+}
+
+fn region_frames(region: &MemoryRegion) -> u64 {
+    region.range.end_frame_number - region.range.start_frame_number
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kib = |frames: u64| frames * 4;
+        write!(
+            f,
+            "total {} KiB, usable {} KiB, used {} KiB, free {} KiB, reserved {} KiB",
+            kib(self.total_frames),
+            kib(self.usable_frames),
+            kib(self.used_frames),
+            kib(self.free_frames()),
+            kib(self.reserved_frames)
+        )
+    }
+}
+
+/// Records the bootloader's memory map so it can be queried later.
+pub fn init(memory_map: &'static MemoryMap) {
+    *MEMORY_MAP.lock() = Some(memory_map);
+}
+
+/// Returns the regions of the memory map passed to `init`.
+pub fn regions() -> &'static [MemoryRegion] {
+    MEMORY_MAP.lock().map(|map| &map[..]).unwrap_or(&[])
+}
+
+/// Returns the current frame counts.
+///
+/// None until both `init` and `memory::init_kernel_memory` have been called.
+pub fn stats() -> Option<MemoryStats> {
+    let memory_map = (*MEMORY_MAP.lock())?;
+    let free_frames = with_kernel_memory(|memory| memory.frame_allocator.free_frames())?;
+    Some(MemoryStats::from_regions(memory_map, free_frames as u64))
+}
+
+/// Prints the frame counts and every region of the memory map.
+pub fn print() {
+    match stats() {
+        Some(stats) => println!("memory: {}", stats),
+        None => println!("memory: not initialized"),
+    }
+    for region in regions() {
+        println!(
+            "  {:#012x}..{:#012x} {:?}",
+            region.range.start_addr(),
+            region.range.end_addr(),
+            region.region_type
+        );
+    }
+}
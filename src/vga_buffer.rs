@@ -1,7 +1,9 @@
+use crate::memory::mmio::{ioremap, CacheMode, MmioError};
 use core::fmt; // Allow Rusts formatting macros
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile; // Ensure buffer writes don't get optimized away
+use x86_64::PhysAddr;
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
@@ -160,6 +162,23 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Moves the writer off the bootloader's identity mapping of the VGA buffer onto
+/// a write-combining `ioremap` mapping.
+///
+/// Needs `memory::init_kernel_memory` to be called first.
+pub fn remap() -> Result<(), MmioError> {
+    use x86_64::instructions::interrupts;
+
+    let size = core::mem::size_of::<Buffer>() as u64;
+    let mmio = unsafe { ioremap(PhysAddr::new(0xb8000), size, CacheMode::WriteCombining)? };
+    interrupts::without_interrupts(|| {
+        WRITER.lock().buffer = unsafe { &mut *mmio.as_ptr(0) };
+    });
+    // the writer uses it until the kernel stops, so never unmap it
+    core::mem::forget(mmio);
+    Ok(())
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::address_space::{AddressSpace, USER_SPACE_START};
+use rust_os::memory::mmio::{ioremap, CacheMode};
+use rust_os::memory::region::{self, RegionKind};
+use rust_os::memory::stack;
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+const VGA_BUFFER: u64 = 0xb8000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|memory| memory.frame_allocator.free_frames()).unwrap()
+}
+
+fn map_user_page(space: &mut AddressSpace, page: Page<Size4KiB>) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::with_kernel_memory(|memory| {
+        memory::map_page(
+            page,
+            flags,
+            &mut space.mapper(),
+            &mut memory.frame_allocator,
+        )
+    })
+    .unwrap()
+    .unwrap();
+}
+
+#[test_case]
+fn user_mappings_are_private() {
+    let page = Page::containing_address(VirtAddr::new(USER_SPACE_START));
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let mut first = AddressSpace::new().unwrap();
+    let mut second = AddressSpace::new().unwrap();
+    map_user_page(&mut first, page);
+    map_user_page(&mut second, page);
+
+    unsafe {
+        first.activate();
+        ptr.write_volatile(1);
+        second.activate();
+        ptr.write_volatile(2);
+        first.activate();
+        assert_eq!(ptr.read_volatile(), 1);
+        second.activate();
+        assert_eq!(ptr.read_volatile(), 2);
+        memory::address_space::activate_kernel_address_space();
+    }
+
+    // not mapped in the kernel's own tables
+    let translated =
+        memory::with_kernel_memory(|memory| memory.mapper.translate_addr(page.start_address()));
+    assert_eq!(translated, Some(None));
+}
+
+#[test_case]
+fn kernel_mappings_are_shared() {
+    let value = 42;
+    let space = AddressSpace::new().unwrap();
+    unsafe { space.activate() };
+    // stack and code are still reachable
+    assert_eq!(unsafe { core::ptr::read_volatile(&value) }, 42);
+    // dropping the active address space switches back to the kernel's tables
+    drop(space);
+    assert_eq!(unsafe { core::ptr::read_volatile(&value) }, 42);
+}
+
+#[test_case]
+fn later_kernel_mappings_are_shared() {
+    let space = AddressSpace::new().unwrap();
+
+    // all of them end up in the region manager's dynamic window, which had no level 4
+    // entry of its own before init_kernel_memory
+    let mmio = unsafe { ioremap(PhysAddr::new(VGA_BUFFER), 4096, CacheMode::Uncached) }.unwrap();
+    let region = region::allocate(4096, 4096, RegionKind::Other).unwrap();
+    let page = Page::<Size4KiB>::containing_address(region.start);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::with_kernel_memory(|memory| {
+        memory::map_page(page, flags, &mut memory.mapper, &mut memory.frame_allocator)
+    })
+    .unwrap()
+    .unwrap();
+
+    let stack = stack::allocate(4).unwrap();
+
+    let ptr: *mut u64 = region.start.as_mut_ptr();
+    let stack_ptr: *mut u64 = (stack.top() - 8u64).as_mut_ptr();
+    let vga = mmio.read::<u16>(0);
+    unsafe {
+        space.activate();
+        ptr.write_volatile(42);
+        stack_ptr.write_volatile(43);
+        assert_eq!(mmio.read::<u16>(0), vga);
+        memory::address_space::activate_kernel_address_space();
+        assert_eq!(ptr.read_volatile(), 42);
+        assert_eq!(stack_ptr.read_volatile(), 43);
+    }
+
+    memory::with_kernel_memory(|memory| {
+        memory::unmap_page(page, &mut memory.mapper, &mut memory.frame_allocator)
+    })
+    .unwrap()
+    .unwrap();
+    region::release(region.start).unwrap();
+}
+
+#[test_case]
+fn drop_frees_frames() {
+    let free_before = free_frames();
+    let mut space = AddressSpace::new().unwrap();
+    for i in 0..16 {
+        let addr = VirtAddr::new(USER_SPACE_START + i * 0x20_0000);
+        map_user_page(&mut space, Page::containing_address(addr));
+    }
+    assert!(free_frames() < free_before);
+    drop(space);
+    assert_eq!(free_frames(), free_before);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
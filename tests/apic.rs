@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::interrupts::ioapic::Redirection;
+use rust_os::interrupts::{self, InterruptIndex, PICS};
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use x86_64::instructions::hlt;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn timer_ticks_through_pic() {
+    assert!(interrupts::local_apic().is_none());
+    wait_for_ticks(3);
+}
+
+#[test_case]
+fn switch_to_apic() {
+    interrupts::init_apic().unwrap();
+    assert!(interrupts::local_apic().is_some());
+    assert_eq!(unsafe { PICS.lock().read_masks() }, [0xff, 0xff]);
+    let io_apic = interrupts::IO_APIC.lock();
+    // ISA IRQs at least
+    assert!(io_apic.as_ref().unwrap().inputs() >= 16);
+}
+
+#[test_case]
+fn keyboard_routed_through_ioapic() {
+    let local_apic = interrupts::local_apic().unwrap();
+    let io_apic = interrupts::IO_APIC.lock();
+    let io_apic = io_apic.as_ref().unwrap();
+    let keyboard = Redirection {
+        vector: InterruptIndex::Keyboard as u8,
+        apic_id: local_apic.id(),
+        masked: false,
+    };
+    assert_eq!(io_apic.redirection(1), keyboard);
+    // the PIT stays masked, the APIC timer replaces it
+    assert!(io_apic.redirection(0).masked);
+}
+
+#[test_case]
+fn timer_ticks_through_apic() {
+    wait_for_ticks(3);
+}
+
+#[test_case]
+fn self_ipi_reaches_handler() {
+    let local_apic = interrupts::local_apic().unwrap();
+    // stop the timer, so only the IPI can tick
+    local_apic.stop_timer();
+    let ticks = interrupts::timer_ticks();
+    // the timer handler doubles as a target, an IPI looks like any other interrupt
+    local_apic.send_self_ipi(InterruptIndex::Timer as u8);
+    while interrupts::timer_ticks() == ticks {
+        core::hint::spin_loop();
+    }
+}
+
+// times out instead of returning if the timer stopped, e.g. because an
+// interrupt wasn't acknowledged
+fn wait_for_ticks(count: u64) {
+    let ticks = interrupts::timer_ticks();
+    while interrupts::timer_ticks() < ticks + count {
+        hlt();
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
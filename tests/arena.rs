@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::allocator::arena::Arena;
+use rust_os::memory::{self, buddy::BuddyFrameAllocator, region};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    // no kernel heap, everything here lives in arenas
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn allocations_come_from_the_arena() {
+    let arena = Arena::new(4096).unwrap();
+    let value = Box::new_in(41u64, &arena);
+    let addr = &*value as *const u64 as u64;
+    assert!(addr >= arena.start().as_u64());
+    assert!(addr < arena.start().as_u64() + arena.capacity() as u64);
+    assert_eq!(*value + 1, 42);
+}
+
+#[test_case]
+fn vec_grows_in_place() {
+    let arena = Arena::new(64 * 1024).unwrap();
+    let mut vec = Vec::new_in(&arena);
+    for i in 0..1000u64 {
+        vec.push(i);
+    }
+    assert!(vec.iter().enumerate().all(|(i, &v)| v == i as u64));
+    // no old copies of the buffer left behind
+    assert_eq!(arena.used(), vec.capacity() * 8);
+}
+
+#[test_case]
+fn exhausted_arena_returns_error() {
+    let arena = Arena::new(4096).unwrap();
+    assert!(Box::try_new_in([0u8; 4096], &arena).is_ok());
+    assert!(Box::try_new_in(0u8, &arena).is_err());
+}
+
+#[test_case]
+fn reset_frees_everything() {
+    let mut arena = Arena::new(4096).unwrap();
+    for _ in 0..2 {
+        let mut vec = Vec::with_capacity_in(4096, &arena);
+        vec.resize(4096, 1u8);
+        core::mem::forget(vec);
+        assert_eq!(arena.used(), 4096);
+        arena.reset();
+        assert_eq!(arena.used(), 0);
+    }
+}
+
+#[test_case]
+fn drop_unmaps_the_region() {
+    let arena = Arena::new(3 * 4096).unwrap();
+    let start = arena.start();
+    assert!(region::find(start).is_some());
+    drop(arena);
+    assert!(region::find(start).is_none());
+    // the same range can be handed out again
+    let arena = Arena::new(3 * 4096).unwrap();
+    let mut vec = Vec::with_capacity_in(3 * 4096, &arena);
+    vec.resize(3 * 4096, 7u8);
+    assert!(vec.iter().all(|&b| b == 7));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
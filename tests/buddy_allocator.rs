@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::buddy::{order_for_size, BuddyFrameAllocator};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn single_frames() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let free_before = frame_allocator.free_frames();
+    let first: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    let second: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+    unsafe {
+        frame_allocator.deallocate_frame(first);
+        frame_allocator.deallocate_frame(second);
+    }
+    assert_eq!(frame_allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn contiguous_run_is_aligned_and_below_limit() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let order = order_for_size(64 * 1024);
+    assert_eq!(order, 4);
+    let limit = PhysAddr::new(4 << 30);
+    let range = frame_allocator.allocate_contiguous(order, limit).unwrap();
+    assert_eq!(range.end - range.start, 16);
+    assert!(range.start.start_address().is_aligned(64 * 1024u64));
+    assert!(range.end.start_address() <= limit);
+    unsafe { frame_allocator.deallocate_contiguous(range) };
+}
+
+#[test_case]
+fn freed_buddies_merge() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let free_before = frame_allocator.free_frames();
+    // take two halves of a block apart and give them back one by one
+    let limit = PhysAddr::new(u64::MAX);
+    let range = frame_allocator.allocate_contiguous(5, limit).unwrap();
+    unsafe { frame_allocator.deallocate_contiguous(range) };
+    let first = frame_allocator.allocate_contiguous(4, limit).unwrap();
+    let second = frame_allocator.allocate_contiguous(4, limit).unwrap();
+    unsafe {
+        frame_allocator.deallocate_contiguous(first);
+        frame_allocator.deallocate_contiguous(second);
+    }
+    assert_eq!(frame_allocator.free_frames(), free_before);
+    // merged block can be handed out as a whole again
+    let merged = frame_allocator.allocate_contiguous(5, limit).unwrap();
+    assert_eq!(merged.start, range.start);
+    unsafe { frame_allocator.deallocate_contiguous(merged) };
+}
+
+#[test_case]
+fn impossible_limit_fails() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    // frame zero is never useable, so nothing fits below one frame
+    assert!(frame_allocator
+        .allocate_contiguous(0, PhysAddr::new(4096))
+        .is_none());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
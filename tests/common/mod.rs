@@ -0,0 +1,58 @@
+// helpers shared by the integration tests, pulled in with `mod common;`
+// each test only uses some of them
+#![allow(dead_code)]
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+const MESSAGE_BUFFER_SIZE: usize = 512;
+const LOCAL_HEAP_SIZE: usize = 64 * 1024;
+
+#[repr(align(4096))]
+struct LocalHeap([u8; LOCAL_HEAP_SIZE]);
+
+static mut LOCAL_HEAP: LocalHeap = LocalHeap([0; LOCAL_HEAP_SIZE]);
+
+/// Returns the start of a static buffer of `size` bytes, for allocators that
+/// are tested apart from the global heap.
+///
+/// Every call hands out the same buffer. That's fine because tests run one
+/// after another, but an allocator must not be used after the next call.
+pub fn local_heap(size: usize) -> usize {
+    assert!(
+        size <= LOCAL_HEAP_SIZE,
+        "local heap is only {} bytes",
+        LOCAL_HEAP_SIZE
+    );
+    unsafe { (*core::ptr::addr_of_mut!(LOCAL_HEAP)).0.as_mut_ptr() as usize }
+}
+
+/// Returns true if the panic described by `info` mentions `text`.
+///
+/// For tests that expect a specific panic. Only the first 512 bytes of the
+/// formatted panic are searched.
+pub fn panic_message_contains(info: &PanicInfo, text: &str) -> bool {
+    let mut buffer = MessageBuffer {
+        bytes: [0; MESSAGE_BUFFER_SIZE],
+        len: 0,
+    };
+    write!(buffer, "{}", info).ok();
+    buffer.bytes[..buffer.len]
+        .windows(text.len())
+        .any(|window| window == text.as_bytes())
+}
+
+// formats into a fixed buffer, silently cutting off what doesn't fit
+struct MessageBuffer {
+    bytes: [u8; MESSAGE_BUFFER_SIZE],
+    len: usize,
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::address_space::{self, AddressSpace, USER_SPACE_START};
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    // kernel writes to shared pages only fault with write protection on
+    unsafe { memory::protect::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|memory| memory.frame_allocator.free_frames()).unwrap()
+}
+
+fn map_user_page(space: &mut AddressSpace, page: Page<Size4KiB>) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::with_kernel_memory(|memory| {
+        memory::map_page(
+            page,
+            flags,
+            &mut space.mapper(),
+            &mut memory.frame_allocator,
+        )
+    })
+    .unwrap()
+    .unwrap();
+}
+
+#[test_case]
+fn writes_are_private_after_duplicate() {
+    let page = Page::containing_address(VirtAddr::new(USER_SPACE_START));
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let mut parent = AddressSpace::new().unwrap();
+    map_user_page(&mut parent, page);
+
+    unsafe {
+        parent.activate();
+        ptr.write_volatile(1);
+        let child = parent.duplicate().unwrap();
+
+        child.activate();
+        assert_eq!(ptr.read_volatile(), 1);
+        ptr.write_volatile(2);
+
+        parent.activate();
+        assert_eq!(ptr.read_volatile(), 1);
+        // last sharer left, so this one is made writable in place
+        ptr.write_volatile(3);
+
+        child.activate();
+        assert_eq!(ptr.read_volatile(), 2);
+        address_space::activate_kernel_address_space();
+    }
+}
+
+#[test_case]
+fn shared_frames_are_freed_once() {
+    let free_before = free_frames();
+    let mut parent = AddressSpace::new().unwrap();
+    for i in 0..4 {
+        let addr = VirtAddr::new(USER_SPACE_START + i * 0x1000);
+        map_user_page(&mut parent, Page::containing_address(addr));
+    }
+    let child = parent.duplicate().unwrap();
+    let grandchild = parent.duplicate().unwrap();
+
+    drop(parent);
+    drop(grandchild);
+    drop(child);
+    assert_eq!(free_frames(), free_before);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rust_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_debug_exception() {
+    // int1 raises #DB without touching the debug registers
+    unsafe { core::arch::asm!("int1", options(nomem, nostack)) };
+}
+
+#[test_case]
+fn test_nmi() {
+    unsafe { core::arch::asm!("int 2", options(nomem, nostack)) };
+}
+
+#[test_case]
+fn registers_survive_exception() {
+    // the entry stub saves and restores them around the Rust handler, which
+    // is free to use all of them
+    let (mut rax, mut rcx, mut rsi, mut r11) = (1u64, 2u64, 3u64, 4u64);
+    unsafe {
+        core::arch::asm!(
+            "int1",
+            inout("rax") rax,
+            inout("rcx") rcx,
+            inout("rsi") rsi,
+            inout("r11") r11,
+            options(nomem, nostack)
+        )
+    };
+    assert_eq!((rax, rcx, rsi, r11), (1, 2, 3, 4));
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::buddy::BuddyFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn allocate_distinct_frames() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let first: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    let second: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+    unsafe {
+        frame_allocator.deallocate_frame(first);
+        frame_allocator.deallocate_frame(second);
+    }
+}
+
+#[test_case]
+fn deallocated_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let free_before = frame_allocator.free_frames();
+    let frame: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(frame_allocator.free_frames(), free_before - 1);
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(frame_allocator.free_frames(), free_before);
+    assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn many_frames_without_exhaustion() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    // far more than physical memory holds if frames were never returned
+    for _ in 0..1_000_000 {
+        let frame: PhysFrame = frame_allocator.allocate_frame().unwrap();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
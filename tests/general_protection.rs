@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use common::panic_message_contains;
+use core::panic::PanicInfo;
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+mod common;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("general_protection::invalid_selector...\t");
+    rust_os::init();
+
+    // index 0x246 (582) is far past the end of the GDT
+    // RAX holds a marker to find in the register dump
+    unsafe {
+        core::arch::asm!(
+            "mov ds, {0:x}",
+            in(reg) 0x1230u64,
+            in("rax") 0x0123_4567_89ab_cdefu64,
+            options(nostack)
+        )
+    };
+
+    serial_println!("[no fault]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // the #GP handler panics, not the double fault handler it used to escalate
+    // to, and names the selector
+    if panic_message_contains(info, "GENERAL PROTECTION FAULT")
+        && panic_message_contains(info, "Gdt index 582")
+        && panic_message_contains(info, "RAX: 0x0123456789abcdef")
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n{}", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
@@ -6,24 +6,30 @@
 
 extern crate alloc;
 
+use alloc::alloc::{GlobalAlloc, Layout};
 use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use rust_os::allocator::HEAP_SIZE;
+use rust_os::allocator::linked_list::{FitPolicy, LinkedListAllocator};
+use rust_os::allocator::{self, leak, Locked, HEAP_MAX_SIZE, HEAP_SIZE};
+
+mod common;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use rust_os::allocator;
-    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use rust_os::memory::{self, buddy::BuddyFrameAllocator};
     use x86_64::VirtAddr;
 
     rust_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -66,6 +72,249 @@ fn many_boxes_long_lived() {
     assert_eq!(*long_lived, 1);
 }
 
+#[test_case]
+fn grows_past_initial_mapping() {
+    // larger than the eagerly mapped part, so pages have to be mapped on demand
+    let n = 4 * HEAP_SIZE;
+    let vec = vec![1u8; n];
+    assert_eq!(vec.iter().map(|&x| x as usize).sum::<usize>(), n);
+    assert!(n < HEAP_MAX_SIZE);
+}
+
+#[test_case]
+fn untouched_heap_page_is_mapped_on_fault() {
+    use rust_os::memory;
+    use x86_64::structures::paging::Translate;
+    use x86_64::VirtAddr;
+
+    // the last page of the heap region, far above anything handed out so far
+    let addr = VirtAddr::new((allocator::heap_start() + HEAP_MAX_SIZE - 4096) as u64);
+    let translate = || memory::with_kernel_memory(|m| m.mapper.translate_addr(addr)).unwrap();
+    assert_eq!(translate(), None);
+
+    // faults, the handler maps a zeroed frame and the read is retried
+    let value = unsafe { addr.as_ptr::<u64>().read_volatile() };
+    assert_eq!(value, 0);
+    assert!(translate().is_some());
+}
+
+struct Node {
+    next: Option<Box<Node>>,
+}
+
+#[test_case]
+fn freed_small_blocks_are_reused() {
+    // fill the heap with small blocks until it has to grow
+    let size_before = allocator::heap_size();
+    let mut list = None;
+    while allocator::heap_size() == size_before {
+        list = Some(Box::new(Node { next: list }));
+    }
+    // iterative drop, recursion would overflow the stack
+    while let Some(mut node) = list {
+        list = node.next.take();
+    }
+
+    // only fits if the freed small blocks are merged again
+    let size = allocator::heap_size();
+    let vec = vec![1u8; size / 2];
+    assert_eq!(vec.len(), size / 2);
+    assert_eq!(allocator::heap_size(), size);
+}
+
+#[test_case]
+fn stats_track_live_allocations() {
+    let before = allocator::heap_stats();
+    let value = Box::new([0u8; 100]);
+    let during = allocator::heap_stats();
+    assert_eq!(during.live_allocations(), before.live_allocations() + 1);
+    assert_eq!(during.bytes_in_use, before.bytes_in_use + 100);
+    assert!(during.peak_bytes_in_use >= during.bytes_in_use);
+
+    drop(value);
+    let after = allocator::heap_stats();
+    assert_eq!(after.live_allocations(), before.live_allocations());
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+}
+
+#[test_case]
+fn leak_tracking_reports_outstanding_allocations() {
+    leak::enable();
+    let checkpoint = leak::checkpoint();
+    let tag = rust_os::alloc_tag!();
+    assert!(tag.starts_with("tests/heap_allocation.rs:"));
+    let freed = leak::with_tag("freed", || Box::new(1u64));
+    let leaked = leak::with_tag(tag, || Box::leak(Box::new(2u32)));
+    drop(freed);
+
+    let mut outstanding = 0;
+    leak::outstanding_since(checkpoint, |record| {
+        assert_eq!(record.addr, leaked as *mut u32 as usize);
+        assert_eq!(record.size, 4);
+        assert_eq!(record.tag, tag);
+        outstanding += 1;
+    });
+    assert_eq!(outstanding, 1);
+    leak::disable();
+}
+
+const LOCAL_HEAP_SIZE: usize = 4096;
+
+// linked list allocator over the local test heap, separate from the global heap
+fn local_allocator(policy: FitPolicy) -> Locked<LinkedListAllocator> {
+    let allocator = Locked::new(LinkedListAllocator::with_policy(policy));
+    let heap_start = common::local_heap(LOCAL_HEAP_SIZE);
+    unsafe { allocator.lock().init(heap_start, LOCAL_HEAP_SIZE) };
+    allocator
+}
+
+#[test_case]
+fn fragmented_heap_is_merged_again() {
+    let allocator = local_allocator(FitPolicy::FirstFit);
+    let small = Layout::from_size_align(256, 8).unwrap();
+    let mut blocks = Vec::new();
+    loop {
+        let ptr = unsafe { allocator.alloc(small) };
+        if ptr.is_null() {
+            break;
+        }
+        blocks.push(ptr);
+    }
+    assert!(blocks.len() > 1);
+
+    // free every other block first, so no freed neighbour exists at that point
+    let (odd, even): (Vec<_>, Vec<_>) = blocks.iter().enumerate().partition(|(i, _)| i % 2 == 1);
+    for (_, &ptr) in odd.into_iter().chain(even) {
+        unsafe { allocator.dealloc(ptr, small) };
+    }
+
+    let large = Layout::from_size_align(LOCAL_HEAP_SIZE, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(large) };
+    assert!(!ptr.is_null());
+    unsafe { allocator.dealloc(ptr, large) };
+}
+
+// frees two holes: 512 bytes at the start and 128 bytes further up
+fn allocate_with_holes(allocator: &Locked<LinkedListAllocator>) -> (*mut u8, *mut u8) {
+    let layout = |size| Layout::from_size_align(size, 8).unwrap();
+    unsafe {
+        let large_hole = allocator.alloc(layout(512));
+        allocator.alloc(layout(64));
+        let small_hole = allocator.alloc(layout(128));
+        allocator.alloc(layout(64));
+        allocator.dealloc(large_hole, layout(512));
+        allocator.dealloc(small_hole, layout(128));
+        (large_hole, small_hole)
+    }
+}
+
+#[test_case]
+fn first_fit_takes_lowest_region() {
+    let allocator = local_allocator(FitPolicy::FirstFit);
+    let (large_hole, _) = allocate_with_holes(&allocator);
+    let ptr = unsafe { allocator.alloc(Layout::from_size_align(100, 8).unwrap()) };
+    assert_eq!(ptr, large_hole);
+}
+
+#[test_case]
+fn best_fit_takes_smallest_region() {
+    let allocator = local_allocator(FitPolicy::BestFit);
+    let (_, small_hole) = allocate_with_holes(&allocator);
+    let ptr = unsafe { allocator.alloc(Layout::from_size_align(100, 8).unwrap()) };
+    assert_eq!(ptr, small_hole);
+}
+
+#[test_case]
+fn reclaimed_blocks_serve_large_allocation() {
+    use rust_os::allocator::fixed_size_block::FixedSizeBlockAllocator;
+
+    // outside of the heap region, so this allocator can't grow
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    let heap_start = common::local_heap(LOCAL_HEAP_SIZE);
+    unsafe { allocator.lock().init(heap_start, LOCAL_HEAP_SIZE) };
+
+    // use all of it up for small blocks, which then sit on their free list
+    let small = Layout::from_size_align(64, 64).unwrap();
+    let mut blocks = Vec::new();
+    loop {
+        let ptr = unsafe { allocator.alloc(small) };
+        if ptr.is_null() {
+            break;
+        }
+        blocks.push(ptr);
+    }
+    for ptr in blocks {
+        unsafe { allocator.dealloc(ptr, small) };
+    }
+
+    // the fallback allocator is empty, so this only works by reclaiming them
+    let large = Layout::from_size_align(2048, 8).unwrap();
+    assert!(!unsafe { allocator.alloc(large) }.is_null());
+}
+
+#[test_case]
+fn realloc_grows_into_free_neighbour() {
+    let allocator = local_allocator(FitPolicy::FirstFit);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        let neighbour = allocator.alloc(layout);
+        let blocker = allocator.alloc(layout);
+        allocator.dealloc(neighbour, layout);
+
+        ptr.write_bytes(0x42, 64);
+        let grown = allocator.realloc(ptr, layout, 128);
+        assert_eq!(grown, ptr);
+
+        // the allocation behind it is in the way now
+        let grown_layout = Layout::from_size_align(128, 8).unwrap();
+        let moved = allocator.realloc(grown, grown_layout, 256);
+        assert_ne!(moved, ptr);
+        assert!((0..64).all(|i| *moved.add(i) == 0x42));
+
+        allocator.dealloc(moved, Layout::from_size_align(256, 8).unwrap());
+        allocator.dealloc(blocker, layout);
+    }
+}
+
+#[test_case]
+fn realloc_shrinks_in_place() {
+    let allocator = local_allocator(FitPolicy::FirstFit);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        let shrunk = allocator.realloc(ptr, layout, 64);
+        assert_eq!(shrunk, ptr);
+
+        // the cut off part is free again
+        let next = allocator.alloc(Layout::from_size_align(128, 8).unwrap());
+        assert_eq!(next, ptr.add(64));
+    }
+}
+
+#[test_case]
+fn realloc_keeps_contents_and_stats() {
+    let before = allocator::heap_stats();
+    let layout = Layout::from_size_align(20, 4).unwrap();
+    unsafe {
+        let ptr = alloc::alloc::alloc(layout);
+        for i in 0..20 {
+            *ptr.add(i) = i as u8;
+        }
+        let ptr = alloc::alloc::realloc(ptr, layout, 28);
+        let ptr = alloc::alloc::realloc(ptr, Layout::from_size_align(28, 4).unwrap(), 3000);
+        assert!((0..20).all(|i| *ptr.add(i) == i as u8));
+        assert_eq!(
+            allocator::heap_stats().bytes_in_use,
+            before.bytes_in_use + 3000
+        );
+        alloc::alloc::dealloc(ptr, Layout::from_size_align(3000, 4).unwrap());
+    }
+    let after = allocator::heap_stats();
+    assert_eq!(after.live_allocations(), before.live_allocations());
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     rust_os::test_panic_handler(info)
@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::allocator::debug::{self, HeapErrorKind, POISON_BYTE, RED_ZONE_BYTE};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[repr(align(64))]
+struct Buffer([u8; 256]);
+
+static mut BUFFER: Buffer = Buffer([0; 256]);
+
+// a debug allocation of `layout` set up in a static buffer instead of the heap
+fn allocation(layout: Layout) -> *mut u8 {
+    assert!(debug::outer_layout(layout).size() <= 256);
+    unsafe { debug::init_allocation(BUFFER.0.as_mut_ptr(), layout) }
+}
+
+#[test_case]
+fn boxes_still_work() {
+    let value = Box::new([1u64; 4]);
+    assert_eq!(value.iter().sum::<u64>(), 4);
+}
+
+#[test_case]
+fn freed_memory_is_poisoned() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptr = allocation(layout);
+    unsafe {
+        debug::check_free(ptr, layout).unwrap();
+        assert!((0..32).all(|i| *ptr.add(i) == POISON_BYTE));
+    }
+}
+
+#[test_case]
+fn double_free_is_detected() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptr = allocation(layout);
+    unsafe {
+        debug::check_free(ptr, layout).unwrap();
+        let err = debug::check_free(ptr, layout).unwrap_err();
+        assert_eq!(err.kind, HeapErrorKind::DoubleFree);
+        assert_eq!(err.addr, ptr as usize);
+    }
+}
+
+#[test_case]
+fn mismatched_layout_is_detected() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptr = allocation(layout);
+    let wrong = Layout::from_size_align(16, 8).unwrap();
+    let err = unsafe { debug::check_free(ptr, wrong) }.unwrap_err();
+    assert_eq!(
+        err.kind,
+        HeapErrorKind::LayoutMismatch { allocated: layout }
+    );
+}
+
+#[test_case]
+fn overflow_into_red_zone_is_detected() {
+    let layout = Layout::from_size_align(20, 4).unwrap();
+    let ptr = allocation(layout);
+    unsafe {
+        assert_eq!(*ptr.add(20), RED_ZONE_BYTE);
+        *ptr.add(21) = 0;
+        let err = debug::check_free(ptr, layout).unwrap_err();
+        assert_eq!(err.kind, HeapErrorKind::RedZone { offset: 21 });
+    }
+}
+
+#[test_case]
+fn underflow_into_red_zone_is_detected() {
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let ptr = allocation(layout);
+    assert_eq!(ptr as usize % 64, 0);
+    unsafe {
+        *ptr.sub(1) = 0;
+        let err = debug::check_free(ptr, layout).unwrap_err();
+        assert_eq!(err.kind, HeapErrorKind::RedZone { offset: -1 });
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::region::{self, RegionKind};
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    OffsetPageTable, Page, PageSize, PageTableFlags, Size1GiB, Size2MiB, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BuddyFrameAllocator)>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    loop {}
+}
+
+fn flags() -> PageTableFlags {
+    PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+}
+
+#[test_case]
+fn map_and_unmap_2mib_page() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+    let free_before = frame_allocator.free_frames();
+
+    let region = region::allocate(Size2MiB::SIZE, Size2MiB::SIZE, RegionKind::Other).unwrap();
+    let page = Page::<Size2MiB>::containing_address(region.start);
+    let frame = memory::map_page(page, flags(), mapper, frame_allocator).unwrap();
+    assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+
+    // both ends of the page are backed by the same huge frame
+    let start: *mut u64 = page.start_address().as_mut_ptr();
+    let last: *mut u64 = (page.start_address() + Size2MiB::SIZE - 8u64).as_mut_ptr();
+    unsafe {
+        start.write_volatile(1);
+        last.write_volatile(2);
+        assert_eq!(start.read_volatile() + last.read_volatile(), 3);
+    }
+
+    memory::unmap_page(page, mapper, frame_allocator).unwrap();
+    assert_eq!(frame_allocator.free_frames(), free_before);
+    region::release(region.start).unwrap();
+}
+
+#[test_case]
+fn region_uses_huge_pages_where_aligned() {
+    let mut guard = MEMORY.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+    let free_before = frame_allocator.free_frames();
+
+    // one small page in front of a 2 MiB boundary, then one huge page
+    let region = region::allocate(2 * Size2MiB::SIZE, Size2MiB::SIZE, RegionKind::Other).unwrap();
+    let start = region.start + Size2MiB::SIZE - Size4KiB::SIZE;
+    let size = Size4KiB::SIZE + Size2MiB::SIZE;
+    memory::map_region(start, size, flags(), mapper, frame_allocator).unwrap();
+    assert!(mapper.translate_addr(start).is_some());
+    assert!(mapper.translate_addr(start + size - 1u64).is_some());
+
+    memory::unmap_region(start, size, mapper, frame_allocator).unwrap();
+    assert!(mapper.translate_addr(start).is_none());
+    assert_eq!(frame_allocator.free_frames(), free_before);
+    region::release(region.start).unwrap();
+}
+
+#[test_case]
+fn map_1gib_page_if_supported() {
+    if !memory::supports_1gib_pages() {
+        return;
+    }
+    let mut guard = MEMORY.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+
+    let region = region::allocate(Size1GiB::SIZE, Size1GiB::SIZE, RegionKind::Other).unwrap();
+    let page = Page::<Size1GiB>::containing_address(region.start);
+    match memory::map_page(page, flags(), mapper, frame_allocator) {
+        Ok(frame) => {
+            assert!(frame.start_address().is_aligned(Size1GiB::SIZE));
+            // both ends of the page are backed by the same huge frame
+            let start: *mut u64 = page.start_address().as_mut_ptr();
+            let last: *mut u64 = (page.start_address() + Size1GiB::SIZE - 8u64).as_mut_ptr();
+            unsafe {
+                start.write_volatile(1);
+                last.write_volatile(2);
+                assert_eq!(start.read_volatile() + last.read_volatile(), 3);
+            }
+            memory::unmap_page(page, mapper, frame_allocator).unwrap();
+        }
+        // small test machines may simply not have a free gigabyte
+        Err(MapToError::FrameAllocationFailed) => {}
+        Err(err) => panic!("mapping a 1 GiB page failed: {:?}", err),
+    }
+    region::release(region.start).unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::bootinfo::MemoryRegionType;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::{self, buddy::BuddyFrameAllocator, stats};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    stats::init(&boot_info.memory_map);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn counts_add_up() {
+    let stats = stats::stats().unwrap();
+    assert!(stats.usable_frames > 0);
+    assert_eq!(
+        stats.usable_frames + stats.reserved_frames,
+        stats.total_frames
+    );
+    assert!(stats.used_frames <= stats.usable_frames);
+}
+
+#[test_case]
+fn regions_match_usable_frames() {
+    let usable: u64 = stats::regions()
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| r.range.end_frame_number - r.range.start_frame_number)
+        .sum();
+    assert_eq!(usable, stats::stats().unwrap().usable_frames);
+}
+
+#[test_case]
+fn allocation_is_counted_as_used() {
+    let used_before = stats::stats().unwrap().used_frames;
+    let frame: PhysFrame =
+        memory::with_kernel_memory(|memory| memory.frame_allocator.allocate_frame().unwrap())
+            .unwrap();
+    assert_eq!(stats::stats().unwrap().used_frames, used_before + 1);
+
+    memory::with_kernel_memory(|memory| unsafe { memory.frame_allocator.deallocate_frame(frame) });
+    assert_eq!(stats::stats().unwrap().used_frames, used_before);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
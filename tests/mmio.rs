@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{fence, Ordering};
+use rust_os::memory::mmio::{ioremap, CacheMode, MmioError};
+use rust_os::memory::region::{self, RegionKind};
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use rust_os::{println, vga_buffer};
+use x86_64::structures::paging::Translate;
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+const VGA_BUFFER: u64 = 0xb8000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn write_through_mapping() {
+    for mode in [CacheMode::Uncached, CacheMode::WriteCombining] {
+        // second line of the screen, unaligned start on purpose
+        let mmio = unsafe { ioremap(PhysAddr::new(VGA_BUFFER + 160), 160, mode) }.unwrap();
+        mmio.write::<u16>(0, 0x0f21);
+        assert_eq!(mmio.read::<u16>(0), 0x0f21);
+        // identity mapped buffer sees the same memory
+        let vga = (VGA_BUFFER + 160) as *const u16;
+        assert_eq!(unsafe { vga.read_volatile() }, 0x0f21);
+    }
+}
+
+#[test_case]
+fn drop_unmaps() {
+    let mmio = unsafe { ioremap(PhysAddr::new(VGA_BUFFER), 4096, CacheMode::Uncached) }.unwrap();
+    let virt_addr = mmio.virt_addr();
+    assert!(region::find(virt_addr).is_some());
+    drop(mmio);
+
+    assert!(region::find(virt_addr).is_none());
+    let translated = memory::with_kernel_memory(|memory| memory.mapper.translate_addr(virt_addr));
+    assert_eq!(translated, Some(None));
+}
+
+#[test_case]
+fn zero_size_is_rejected() {
+    let result = unsafe { ioremap(PhysAddr::new(VGA_BUFFER), 0, CacheMode::Uncached) };
+    assert!(matches!(result, Err(MmioError::ZeroSize)));
+}
+
+#[test_case]
+fn vga_writer_uses_mmio_mapping() {
+    let mmio_regions = || {
+        let mut count = 0;
+        region::for_each(|region| count += (region.kind == RegionKind::Mmio) as usize);
+        count
+    };
+    let before = mmio_regions();
+    vga_buffer::remap().unwrap();
+    assert_eq!(mmio_regions(), before + 1);
+
+    // second to last row of the screen after the newline
+    let s = "written through the remapped buffer";
+    println!("\n{}", s);
+    // drain the write combining buffers before reading through another mapping
+    fence(Ordering::SeqCst);
+    let row = (VGA_BUFFER + 23 * 160) as *const u16;
+    for (i, c) in s.chars().enumerate() {
+        let screen_char = unsafe { row.add(i).read_volatile() };
+        assert_eq!(screen_char as u8 as char, c);
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os::allocator::linked_list::LinkedListAllocator;
+use rust_os::allocator::{oom, Locked, HEAP_MAX_SIZE};
+
+mod common;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn try_alloc_returns_error() {
+    let layout = Layout::from_size_align(2 * HEAP_MAX_SIZE, 8).unwrap();
+    assert_eq!(oom::try_alloc(layout), Err(oom::AllocError { layout }));
+}
+
+#[test_case]
+fn try_box_moves_value_to_heap() {
+    let value = oom::try_box([7u8; 64]).unwrap();
+    assert!(value.iter().all(|&b| b == 7));
+}
+
+const LOCAL_HEAP_SIZE: usize = 4096;
+
+// stands in for the global heap, which is too big to fill in a test
+static LOCAL: Locked<LinkedListAllocator> = Locked::with_oom_hooks(LinkedListAllocator::new());
+
+// memory the hook may free, 0 once it's gone
+static CACHE: AtomicUsize = AtomicUsize::new(0);
+static HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+const CACHE_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(1024, 8) };
+
+fn drop_cache(_layout: Layout) -> usize {
+    HOOK_CALLS.fetch_add(1, Ordering::Relaxed);
+    match CACHE.swap(0, Ordering::Relaxed) {
+        0 => 0,
+        cache => {
+            unsafe { LOCAL.dealloc(cache as *mut u8, CACHE_LAYOUT) };
+            CACHE_LAYOUT.size()
+        }
+    }
+}
+
+#[test_case]
+fn hooks_run_before_allocation_fails() {
+    let heap_start = common::local_heap(LOCAL_HEAP_SIZE);
+    unsafe { LOCAL.lock().init(heap_start, LOCAL_HEAP_SIZE) };
+
+    let cache = unsafe { LOCAL.alloc(CACHE_LAYOUT) };
+    assert!(!cache.is_null());
+    CACHE.store(cache as usize, Ordering::Relaxed);
+    // fill the rest of the heap
+    let small = Layout::from_size_align(64, 8).unwrap();
+    while !unsafe { LOCAL.alloc(small) }.is_null() {}
+
+    oom::register_hook(drop_cache).unwrap();
+    let ptr = unsafe { LOCAL.alloc(CACHE_LAYOUT) };
+    assert_eq!(ptr, cache);
+    assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 1);
+
+    // nothing left to free, the hooks run once more and give up
+    assert!(unsafe { LOCAL.alloc(CACHE_LAYOUT) }.is_null());
+    assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 2);
+    oom::unregister_hook(drop_cache);
+}
+
+#[test_case]
+fn private_allocators_skip_hooks() {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    let heap_start = common::local_heap(LOCAL_HEAP_SIZE);
+    unsafe { allocator.lock().init(heap_start, LOCAL_HEAP_SIZE) };
+
+    HOOK_CALLS.store(0, Ordering::Relaxed);
+    oom::register_hook(drop_cache).unwrap();
+    let layout = Layout::from_size_align(2 * LOCAL_HEAP_SIZE, 8).unwrap();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 0);
+    oom::unregister_hook(drop_cache);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
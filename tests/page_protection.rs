@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+use rust_os::{allocator, exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// set once the write to kernel text faulted as expected
+static TEXT_WRITE_FAULTED: AtomicBool = AtomicBool::new(false);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // no PIC and interrupts, the test IDT only handles page faults
+    rust_os::gdt::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe { memory::protect::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    init_test_idt();
+
+    serial_print!("page_protection::text_is_not_writable...\t");
+    let text = text_target as usize as *mut u8;
+    unsafe { text.write_volatile(0xc3) };
+
+    panic!("Execution continued after writing to kernel text");
+}
+
+fn text_target() {}
+
+// runs from inside the page fault handler, which is fine since it never returns
+fn execute_heap() {
+    serial_print!("page_protection::heap_is_not_executable...\t");
+    // a single `ret`
+    let code = Box::new([0xc3u8; 16]);
+    let function: fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    function();
+
+    panic!("Execution continued after running heap memory");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert!(error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+
+    if !TEXT_WRITE_FAULTED.swap(true, Ordering::SeqCst) {
+        assert!(error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+        serial_println!("[ok]");
+        execute_heap();
+    } else {
+        assert!(error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH));
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        loop {}
+    }
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::allocator::slab::SlabCache;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rust_os::allocator;
+    use rust_os::memory::{self, buddy::BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn objects_are_distinct_and_aligned() {
+    let mut cache = SlabCache::new("test", 24, 16, None);
+    let objects: Vec<_> = (0..100).map(|_| cache.alloc().unwrap()).collect();
+    for (i, object) in objects.iter().enumerate() {
+        assert_eq!(object.as_ptr() as usize % 16, 0);
+        unsafe { (object.as_ptr() as *mut u64).write(i as u64) };
+    }
+    for (i, object) in objects.iter().enumerate() {
+        assert_eq!(unsafe { (object.as_ptr() as *mut u64).read() }, i as u64);
+    }
+    for object in objects {
+        unsafe { cache.free(object) };
+    }
+}
+
+#[test_case]
+fn free_slabs_go_back_to_heap() {
+    let mut cache = SlabCache::new("test", 64, 8, None);
+    let objects: Vec<_> = (0..200).map(|_| cache.alloc().unwrap()).collect();
+    let stats = cache.stats();
+    assert_eq!(stats.objects_in_use, 200);
+    assert!(stats.slabs > 1);
+
+    for object in objects {
+        unsafe { cache.free(object) };
+    }
+    let stats = cache.stats();
+    assert_eq!(stats.slabs, 0);
+    assert_eq!(stats.objects_in_use, 0);
+    assert_eq!(stats.allocations, 200);
+    assert_eq!(stats.frees, 200);
+}
+
+fn construct(object: *mut u8) {
+    unsafe { (object as *mut u64).write(0xdead_beef) };
+}
+
+#[test_case]
+fn constructed_state_survives_free() {
+    let mut cache = SlabCache::new("test", 8, 8, Some(construct));
+    let first = cache.alloc().unwrap();
+    let second = cache.alloc().unwrap();
+    assert_eq!(unsafe { (first.as_ptr() as *mut u64).read() }, 0xdead_beef);
+    // keeps the slab alive, so `first` is reused instead of constructed again
+    unsafe { cache.free(first) };
+    let again = cache.alloc().unwrap();
+    assert_eq!(again, first);
+    assert_eq!(unsafe { (again.as_ptr() as *mut u64).read() }, 0xdead_beef);
+    unsafe {
+        cache.free(again);
+        cache.free(second);
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}